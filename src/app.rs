@@ -1,10 +1,11 @@
-use std::rc::Rc;
+use std::sync::Arc;
 use std::{mem::size_of, sync::mpsc::Sender};
 
 use std::sync::mpsc::{channel, Receiver};
+use std::time::Duration;
 
 use cpal::Device;
-use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::traits::{DeviceTrait, HostTrait};
 
 use serde::{Serialize, Deserialize};
 
@@ -12,41 +13,130 @@ use serde::{Serialize, Deserialize};
 use windows_sys::Win32::UI::{Input::KeyboardAndMouse::*, WindowsAndMessaging::GetMessageExtraInfo};
 
 use crate::{JGlobalHotkey, JGlobalHotkeyErrors, JGlobalHotkeyManager, JGlobalHotKeyEvent};
+use crate::decoder::{decode_file, JSoundErrors};
+use crate::ducking::JDucking;
+use crate::effects::{EffectChain, EffectSource, JEffect, JLimiter};
+use crate::channel_map::ChannelMapper;
+use crate::engine::{AudioEngine, EngineCommand, JAudioStream};
+use crate::gain::{db_to_gain, GainPan};
+use crate::mixer::{JVoiceEvent, JVoiceStealPolicy, Mixer, SequenceRunId, VoiceFades, VoiceId};
+use crate::pitch::{JPlayback, PitchShift};
+use crate::recorder::{JRecordErrors, JRecording, Recorder};
+use crate::resampler::{JResampleQuality, Resampler};
+use crate::loudness::{analyze, JLoudness};
+use crate::sound::{JLoudnessCache, JSoundEntry, LoopRegion, SoundLevels};
+use crate::source::JFrameSource;
+use crate::sequence::{JSequence, JSequenceStep, SequenceId};
+use crate::trigger::{BindingTarget, HotkeyBinding, JPoolOrder, JTransportAction, JTriggerMode, SoundPool};
 // use winit::window::Window;
 
 #[derive(Debug)]
 struct SoundDataIter {
-    current: *const f32,
-    end: *const f32
+    // shared with the sound, it stays alive while the voice plays even if the sound is unloaded
+    data: Arc<[f32]>,
+    frames: usize,
+    channels: usize,
+    position: usize,
+    loop_region: Option<LoopRegion>,
+    /// jumps back to the loop start left, none loops until the voice is stopped.
+    loops_left: Option<u32>
 }
 
-unsafe impl Send for SoundDataIter {}
-unsafe impl Sync for SoundDataIter {}
-
 impl SoundDataIter {
     
-    fn new(sound_data: &Vec<f32>) -> SoundDataIter {
-        let range = sound_data.as_ptr_range();
+    fn new(sound_data: Arc<[f32]>, channels: usize, loop_region: Option<LoopRegion>, loop_count: Option<u32>) -> SoundDataIter {
         SoundDataIter {
-            current: range.start as *mut f32,
-            end: range.end
+            frames: sound_data.len() / channels.max(1),
+            data: sound_data,
+            channels,
+            position: 0,
+            loop_region,
+            loops_left: loop_count.map(|c| c.saturating_sub(1))
         }
     }
 
     #[inline]
-    fn next_value(&mut self) -> f32 {
-        let value = unsafe { *self.current };
-        if self.current != self.end { unsafe { self.current = self.current.add(1);}}
-        return value;
+    fn sample(&self, frame: usize, channel: usize) -> f32 {
+        self.data[frame * self.channels + channel]
     }
 
-    #[inline]
-    fn is_done(&self) -> bool {
-        self.current == self.end
+    fn is_looping(&self) -> bool {
+        self.loop_region.is_some() && self.loops_left != Some(0)
     }
 }
 
+impl JFrameSource for SoundDataIter {
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
 
+    #[inline]
+    fn next_frame(&mut self, frame: &mut [f32]) -> bool {
+        if let (Some(region), true) = (self.loop_region, self.is_looping()) {
+            if self.position >= region.end {
+                // the crossfade already played the first frames of the section
+                self.position = region.start + region.crossfade;
+                self.loops_left = self.loops_left.map(|l| l - 1);
+            }
+        }
+        if self.position >= self.frames {
+            return false;
+        }
+
+        for (channel, sample) in frame.iter_mut().enumerate() {
+            *sample = self.sample(self.position, channel);
+        }
+        if let (Some(region), true) = (self.loop_region, self.is_looping()) {
+            let fade_start = region.end - region.crossfade;
+            if self.position >= fade_start {
+                // equal power, ambiences are rarely correlated at the seam
+                let offset = self.position - fade_start;
+                let t = (offset as f32 + 0.5) / region.crossfade as f32 * std::f32::consts::FRAC_PI_2;
+                let (fade_out, fade_in) = (t.cos(), t.sin());
+                for (channel, sample) in frame.iter_mut().enumerate() {
+                    *sample = *sample * fade_out + self.sample(region.start + offset, channel) * fade_in;
+                }
+            }
+        }
+        self.position += 1;
+        true
+    }
+
+    fn seek(&mut self, frame: usize) -> bool {
+        self.position = frame.min(self.frames);
+        true
+    }
+
+    fn position(&self) -> Option<usize> {
+        Some(self.position)
+    }
+}
+
+/// Convert a sound to the output device channel layout and sample rate.
+fn build_sound_source(
+    sound_player: SoundDataIter,
+    sound_rate: u32,
+    channels: usize,
+    sample_rate: u32,
+    quality: JResampleQuality,
+    playback: JPlayback
+) -> Box<dyn JFrameSource> {
+    let mut source: Box<dyn JFrameSource> = Box::new(sound_player);
+    // shift and resample with the smallest channel count
+    if source.channels() > channels {
+        source = Box::new(ChannelMapper::new(source, channels));
+    }
+    // always there so the speed and pitch can change while the sound plays
+    source = Box::new(PitchShift::new(source, sound_rate, playback));
+    if sound_rate != sample_rate {
+        source = Box::new(Resampler::new(source, sound_rate, sample_rate, quality));
+    }
+    if source.channels() != channels {
+        source = Box::new(ChannelMapper::new(source, channels));
+    }
+    source
+}
 
 
 trait JSystemInput {
@@ -57,6 +147,7 @@ trait JSystemInput {
 }
 
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum JMouseButton {
     MouseButton1,
     MouseButton2,
@@ -122,13 +213,80 @@ impl JSystemInput for JMouseButton{
 }
 
 pub enum JAppEvent {
-    StopAudio
+    SetVolume(i32),
+    LoudnessAnalyzed(SoundId, JLoudness),
+    /// a stream of the engine `generation` failed, only that stream is rebuilt.
+    AudioStreamError {
+        stream: JAudioStream,
+        generation: usize,
+        reason: String
+    },
+    /// the recording stream failed, what was captured is saved.
+    RecordingError(String)
 }
 
+
+/// What is playing, published to every `JApp::playback_events` receiver.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JPlaybackEvent {
+    Started {
+        voice_id: VoiceId,
+        sound_id: SoundId,
+        duration: Duration
+    },
+    Progress {
+        voice_id: VoiceId,
+        sound_id: SoundId,
+        /// position in the sound, it goes past the duration when the sound loops.
+        position: Duration,
+        duration: Duration
+    },
+    Finished {
+        voice_id: VoiceId,
+        sound_id: SoundId
+    },
+    /// stopped by the user, a choke group or the voice limit.
+    Stopped {
+        voice_id: VoiceId,
+        sound_id: SoundId
+    }
+}
+
+pub const UNITY_VOLUME: i32 = 42;
+pub const MAX_VOLUME: i32 = 56;
+
 #[derive(Serialize, Deserialize)]
+#[serde(default)]
 pub struct JAppState {
     pub current_device: Option<String>,
-    pub stop_audio_on_release: bool
+    /// legacy setting from before the trigger modes, the one shot sound hotkeys behave like hold.
+    pub stop_audio_on_release: bool,
+    /// second output playing the same mix, usually headphones when the primary is a virtual cable.
+    pub monitor_device: Option<String>,
+    /// input mixed into the primary output, with the sounds.
+    pub microphone_device: Option<String>,
+    pub resample_quality: JResampleQuality,
+    pub max_voices: usize,
+    pub voice_steal_policy: JVoiceStealPolicy,
+    /// master volume as shown by the ui, `UNITY_VOLUME` doesn't change the sound level.
+    pub volume: i32,
+    /// volume of the primary output alone, on the same scale.
+    pub output_volume: i32,
+    /// volume of the monitor output alone, on the same scale.
+    pub monitor_volume: i32,
+    /// volume of the microphone in the primary output, on the same scale.
+    pub microphone_volume: i32,
+    pub microphone_muted: bool,
+    /// lower the microphone while sounds play, or the sounds while speaking.
+    pub ducking: Option<JDucking>,
+    pub recording: JRecording,
+    /// fade applied when a sound start, unless the sound has its own.
+    pub fade_in_ms: f32,
+    /// fade applied when a sound is stopped, unless the sound has its own.
+    pub fade_out_ms: f32,
+    /// effects on the sum of every sound, after the volume.
+    pub master_effects: Vec<JEffect>,
+    pub sounds: Vec<JSoundEntry>
 }
 
 
@@ -137,32 +295,133 @@ impl Default for JAppState {
     fn default() -> Self {
         JAppState { 
             current_device: None,
-            stop_audio_on_release: false
+            stop_audio_on_release: false,
+            monitor_device: None,
+            microphone_device: None,
+            resample_quality: JResampleQuality::default(),
+            max_voices: 16,
+            voice_steal_policy: JVoiceStealPolicy::default(),
+            volume: UNITY_VOLUME,
+            output_volume: UNITY_VOLUME,
+            monitor_volume: UNITY_VOLUME,
+            microphone_volume: UNITY_VOLUME,
+            microphone_muted: false,
+            ducking: None,
+            recording: JRecording::default(),
+            fade_in_ms: 0.0,
+            fade_out_ms: 10.0,
+            master_effects: vec![JEffect::Limiter(JLimiter::default())],
+            sounds: Vec::new()
         }
    } 
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct SoundId(usize);
+pub struct SoundId(pub(crate) usize);
+
+
+fn volume_to_gain(volume: i32) -> f32 {
+    volume as f32 / UNITY_VOLUME as f32
+}
+
+
+fn find_input_device(target_name: &str) -> Option<Device> {
+    let devices = cpal::default_host().input_devices().ok()?;
+    for d in devices {
+        if let Ok(name) = d.name() {
+            if name == target_name {
+                return Some(d);
+            }
+        }
+    }
+    None
+}
+
+
+fn find_output_device(target_name: &str) -> Option<Device> {
+    let devices = cpal::default_host().output_devices().ok()?;
+    for d in devices {
+        if let Ok(name) = d.name() {
+            if name == target_name {
+                return Some(d);
+            }
+        }
+    }
+    None
+}
 
 
 struct Sound {
-    data: Vec<f32>,
-    // duration: std::time::Duration,
+    data: Arc<[f32]>,
+    sample_rate: u32,
+    channels: u16,
+    levels: SoundLevels,
+    loudness: Option<JLoudness>,
+    duration: Duration,
     path: String,
     sound_id: SoundId
 }
 
+/// A voice ready to be added to the mixer.
+struct NewVoice {
+    id: VoiceId,
+    sound_id: SoundId,
+    playback: JPlayback,
+    source: Box<dyn JFrameSource>,
+    fades: VoiceFades,
+    choke_group: Option<u32>
+}
+
+/// A voice that didn't finish and wasn't stopped yet.
+struct PlayingVoice {
+    id: VoiceId,
+    sound_id: SoundId,
+    // speed and pitch it started with
+    initial_playback: JPlayback,
+    playback: JPlayback,
+    paused: bool,
+    // fading out, it stays here until the mixer reports its end
+    stopping: bool
+}
+
+impl PlayingVoice {
+
+    fn new(voice: &NewVoice) -> Self {
+        PlayingVoice {
+            id: voice.id,
+            sound_id: voice.sound_id,
+            initial_playback: voice.playback,
+            playback: voice.playback,
+            paused: false,
+            stopping: false
+        }
+    }
+}
+
 pub struct JApp {
     simulate_key_press_on_play: Vec<Box<dyn JSystemInput>>,
     // main_window: Option<Window>,
     sender: Sender<JAppEvent>,
     receiver: Receiver<JAppEvent>,
     pub state: JAppState,
-    sound_stream: Option<Rc<cpal::Stream>>,
+    engine: Option<AudioEngine>,
+    // tells the stream errors of each engine apart
+    engine_generation: usize,
+    inputs_pressed: bool,
     sounds: Vec<Sound>,
     sound_idx_count: usize,
-    hotkey_sound_mapping: Vec<(JGlobalHotkey, SoundId)>
+    voice_idx_count: usize,
+    hotkey_sound_mapping: Vec<HotkeyBinding>,
+    sequences: Vec<(SequenceId, JSequence)>,
+    sequence_idx_count: usize,
+    // sequence inputs waiting for their time, with the run of their sequence
+    scheduled_inputs: Vec<(std::time::Instant, JMouseButton, SequenceRunId)>,
+    // voices of every sequence still playing, by run
+    sequence_runs: Vec<(SequenceRunId, Vec<VoiceId>)>,
+    sequence_run_idx_count: usize,
+    playing_voices: Vec<PlayingVoice>,
+    playback_senders: Vec<Sender<JPlaybackEvent>>,
+    recorder: Option<Recorder>
 }
 
 // https://learn.microsoft.com/en-us/windows-hardware/drivers/audio/virtual-audio-devices
@@ -176,19 +435,60 @@ impl JApp {
             sender: jtx,
             receiver: jrx,
             state: JAppState::default(),
-            sound_stream: None,
+            engine: None,
+            engine_generation: 0,
+            inputs_pressed: false,
             sounds: Vec::new(),
             sound_idx_count: 0,
-            hotkey_sound_mapping: Vec::new()
+            voice_idx_count: 0,
+            hotkey_sound_mapping: Vec::new(),
+            sequences: Vec::new(),
+            sequence_idx_count: 0,
+            scheduled_inputs: Vec::new(),
+            sequence_runs: Vec::new(),
+            sequence_run_idx_count: 0,
+            playing_voices: Vec::new(),
+            playback_senders: Vec::new(),
+            recorder: None
         }
     }
 
-    pub fn register_hoktey_for_sound(&mut self, hotkey: &str, sound_id: SoundId) -> Result<(), String> {
+    pub fn register_hoktey_for_sound(&mut self, hotkey: &str, sound_id: SoundId, mode: JTriggerMode) -> Result<(), String> {
+        self.register_hotkey(hotkey, BindingTarget::Sounds(SoundPool::single(sound_id)), mode)
+    }
+
+    /// Each press of the hotkey plays one of the sounds, `sounds` pairs a sound with its weight.
+    pub fn register_hotkey_for_pool(
+        &mut self,
+        hotkey: &str,
+        sounds: Vec<(SoundId, f32)>,
+        order: JPoolOrder,
+        mode: JTriggerMode
+    ) -> Result<(), String> {
+        self.register_hotkey(hotkey, BindingTarget::Sounds(SoundPool::new(sounds, order)), mode)
+    }
+
+    /// The trigger mode doesn't apply to transport hotkeys.
+    pub fn register_hotkey_for_transport(&mut self, hotkey: &str, action: JTransportAction) -> Result<(), String> {
+        self.register_hotkey(hotkey, BindingTarget::Transport(action), JTriggerMode::OneShot)
+    }
+
+    pub fn register_hotkey_for_sequence(&mut self, hotkey: &str, sequence_id: SequenceId, mode: JTriggerMode) -> Result<(), String> {
+        self.register_hotkey(hotkey, BindingTarget::Sequence(sequence_id), mode)
+    }
+
+    /// Start and stop recording with a hotkey, the hold mode records while it is down.
+    pub fn register_hotkey_for_recording(&mut self, hotkey: &str, mode: JTriggerMode) -> Result<(), String> {
+        self.register_hotkey(hotkey, BindingTarget::Recording, mode)
+    }
+
+    fn register_hotkey(&mut self, hotkey: &str, target: BindingTarget, mode: JTriggerMode) -> Result<(), String> {
         match JGlobalHotkey::try_from(hotkey) {
             Ok(hotkey) => {
                 match JGlobalHotkeyManager::register(&hotkey) {
                     Ok(_) => {
-                        self.hotkey_sound_mapping.push((hotkey, sound_id));
+                        let mode = self.legacy_trigger_mode(&target, mode);
+                        self.hotkey_sound_mapping.push(HotkeyBinding::new(hotkey, target, mode));
                         Ok(())
                     },
                     Err(_) => Err(String::from("Register failed."))
@@ -201,27 +501,112 @@ impl JApp {
         }
     }
 
-    pub fn load_sound(&mut self, path: &str) -> Option<SoundId> {
-        let mut inp_file = std::fs::File::open(std::path::Path::new(path)).unwrap();
-        let (_, data) = wav::read(&mut inp_file).unwrap();
-        match data {
-            wav::BitDepth::ThirtyTwoFloat(v) => {
-                // let len = (v.len() as f64 / header.channel_count as f64) / header.sampling_rate as f64;
-                // duration: std::time::Duration::from_millis((len * 1000.0) as _),
-                let sound_id = SoundId(self.sound_idx_count);
-                self.sound_idx_count += 1;
-                self.sounds.push(
-                    Sound { 
-                        data: v,
-                        path: String::from(path),
-                        sound_id
-                    }
-                );
-                return Some(sound_id);
+    /// Hold instead of one shot for sounds and sequences when the state still asks to stop the audio on release.
+    fn legacy_trigger_mode(&self, target: &BindingTarget, mode: JTriggerMode) -> JTriggerMode {
+        let plays = matches!(target, BindingTarget::Sounds(_) | BindingTarget::Sequence(_));
+        if self.state.stop_audio_on_release && plays && mode == JTriggerMode::OneShot {
+            return JTriggerMode::Hold;
+        }
+        mode
+    }
+
+    pub fn add_sequence(&mut self, sequence: JSequence) -> SequenceId {
+        let sequence_id = SequenceId(self.sequence_idx_count);
+        self.sequence_idx_count += 1;
+        self.sequences.push((sequence_id, sequence));
+        sequence_id
+    }
+
+    pub fn load_sound(&mut self, path: &str) -> Result<SoundId, JSoundErrors> {
+        let sound = self.read_sound(path, SoundId(self.sound_idx_count))?;
+        self.sound_idx_count += 1;
+        let sound_id = sound.sound_id;
+        self.sounds.push(sound);
+        Ok(sound_id)
+    }
+
+    /// Read the file of a sound again, the voices already playing keep the old data.
+    pub fn reload_sound(&mut self, sound_id: SoundId) -> Result<(), JSoundErrors> {
+        let path = match self.get_sound(sound_id) {
+            Some(sound) => sound.path.clone(),
+            None => return Ok(())
+        };
+        let sound = self.read_sound(&path, sound_id)?;
+        if let Some(old) = self.sounds.iter_mut().find(|s| s.sound_id == sound_id) {
+            *old = sound;
+        }
+        Ok(())
+    }
+
+    /// Forget a sound, its voices play until they end and its settings stay in the library.
+    pub fn unload_sound(&mut self, sound_id: SoundId) {
+        self.sounds.retain(|s| s.sound_id != sound_id);
+    }
+
+    fn read_sound(&mut self, path: &str, sound_id: SoundId) -> Result<Sound, JSoundErrors> {
+        let decoded = decode_file(path)?;
+        let frames = decoded.data.len() / (decoded.channels as usize).max(1);
+        let duration = Duration::from_secs_f64(frames as f64 / decoded.sample_rate.max(1) as f64);
+        if !self.state.sounds.iter().any(|e| e.path == path) {
+            self.state.sounds.push(JSoundEntry::new(path));
+        }
+
+        let data: Arc<[f32]> = decoded.data.into();
+        let mut levels = SoundLevels::measure(&data);
+        let cached_loudness = self.state.sounds
+            .iter()
+            .find(|e| e.path == path)
+            .and_then(|e| e.loudness_cache)
+            .filter(|c| c.is_valid_for(path))
+            .map(|c| c.loudness);
+        match cached_loudness.as_ref() {
+            Some(loudness) => levels.set_loudness(loudness),
+            None => {
+                // the analysis go through the whole file, don't block the hotkeys while it run.
+                let sender = self.sender.clone();
+                let data = data.clone();
+                let channels = decoded.channels as usize;
+                let sample_rate = decoded.sample_rate;
+                std::thread::spawn(move || {
+                    let loudness = analyze(&data, channels, sample_rate);
+                    let _ = sender.send(JAppEvent::LoudnessAnalyzed(sound_id, loudness));
+                });
+            }
+        };
+
+        Ok(Sound { 
+            levels,
+            loudness: cached_loudness,
+            duration,
+            data,
+            sample_rate: decoded.sample_rate,
+            channels: decoded.channels,
+            path: String::from(path),
+            sound_id
+        })
+    }
+
+    pub fn sound_duration(&self, sound_id: SoundId) -> Option<Duration> {
+        self.get_sound(sound_id).map(|s| s.duration)
+    }
+
+    /// EBU R128 measure of a sound, `None` until the analysis is done.
+    pub fn sound_loudness(&self, sound_id: SoundId) -> Option<JLoudness> {
+        self.get_sound(sound_id)?.loudness
+    }
+
+    fn set_sound_loudness(&mut self, sound_id: SoundId, loudness: JLoudness) {
+        let path = match self.sounds.iter_mut().find(|s| s.sound_id == sound_id) {
+            Some(sound) => {
+                sound.loudness = Some(loudness);
+                sound.levels.set_loudness(&loudness);
+                sound.path.clone()
             },
-            _ => ()
+            None => return
         };
-        None
+        if let Some(entry) = self.state.sounds.iter_mut().find(|e| e.path == path) {
+            entry.loudness_cache = JLoudnessCache::new(&path, loudness);
+        }
     }
 
     pub fn load_sate(&mut self) {
@@ -235,6 +620,16 @@ impl JApp {
             Err(e) => println!("Couldn't load state: {e:?}")
         }
         println!("Using output device: {}", self.state.current_device.as_ref().unwrap_or(&String::new()));
+        // bindings registered before the state was loaded
+        for index in 0..self.hotkey_sound_mapping.len() {
+            let binding = &self.hotkey_sound_mapping[index];
+            let mode = self.legacy_trigger_mode(&binding.target, binding.mode);
+            self.hotkey_sound_mapping[index].mode = mode;
+        }
+        // the microphone plays without waiting for a sound
+        if self.state.microphone_device.is_some() {
+            self.open_engine();
+        }
     }
 
     pub fn save_state(&self) {
@@ -256,19 +651,69 @@ impl JApp {
 
     pub fn get_output_audio_device(&self) -> Option<Device> {
         if let Some(target_name) = self.state.current_device.as_ref() {
-            if let Ok(devices) = cpal::default_host().output_devices() {
-                for d in devices {
-                    if let Ok(name) = d.name() {
-                        if &name == target_name {
-                            return  Some(d);
-                        }
-                    }
-                }
+            if let Some(d) = find_output_device(target_name) {
+                return  Some(d);
             }
         }
         return cpal::default_host().default_output_device();
     }
 
+    pub fn get_monitor_audio_device(&self) -> Option<Device> {
+        find_output_device(self.state.monitor_device.as_ref()?)
+    }
+
+    pub fn get_microphone_audio_device(&self) -> Option<Device> {
+        find_input_device(self.state.microphone_device.as_ref()?)
+    }
+
+    pub fn get_recording_audio_device(&self) -> Option<Device> {
+        match self.state.recording.device.as_ref() {
+            Some(target_name) => find_input_device(target_name),
+            None => cpal::default_host().default_input_device()
+        }
+    }
+
+    /// Record the recording device until `stop_recording` is called.
+    pub fn start_recording(&mut self) -> Result<(), JRecordErrors> {
+        if self.recorder.is_some() {
+            return Err(JRecordErrors::AlreadyRecording);
+        }
+        let device = self.get_recording_audio_device().ok_or(JRecordErrors::NoDevice)?;
+        self.recorder = Some(Recorder::start(&device, self.sender.clone())?);
+        Ok(())
+    }
+
+    /// Stop recording, save the recording as a wav file and load it as a new sound.
+    pub fn stop_recording(&mut self) -> Result<SoundId, JRecordErrors> {
+        let recorder = self.recorder.take().ok_or(JRecordErrors::NotRecording)?;
+        let settings = &self.state.recording;
+        let recorded = recorder.stop(settings.trim_silence.then_some(settings.silence_db));
+        if recorded.data.is_empty() {
+            return Err(JRecordErrors::Empty);
+        }
+        let time = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default();
+        let path = std::path::Path::new(&settings.directory).join(format!("recording-{}.wav", time.as_millis()));
+        recorded.save_wav(&path)?;
+        self.load_sound(&path.to_string_lossy()).map_err(JRecordErrors::Sound)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    fn toggle_recording(&mut self) {
+        let result = if self.is_recording() {
+            self.stop_recording().map(|sound_id| println!("Recorded sound {sound_id:?}"))
+        } else {
+            self.start_recording()
+        };
+        if let Err(e) = result {
+            println!("{e}");
+        }
+    }
+
     pub fn get_ouptut_audio_devices() -> Vec<String>{
         let host = cpal::default_host();
         if let Ok(devices) = host.output_devices() {
@@ -279,6 +724,183 @@ impl JApp {
 
     pub fn set_output_device(&mut self, device_name: &str) {
         self.state.current_device = Some(String::from(device_name));
+        // the engine will be restarted on the new device on next play.
+        self.close_engine();
+    }
+
+    /// Drop the engine so the next sound reopens it, only the microphone passthrough reopens it right away.
+    fn close_engine(&mut self) {
+        self.engine = None;
+        // the voices died with the stream
+        self.playing_voice_ids()
+            .into_iter()
+            .for_each(|v| self.publish_voice_event(v, JVoiceEvent::Stopped));
+        self.release_inputs();
+        if self.state.microphone_device.is_some() {
+            self.open_engine();
+        }
+    }
+
+    /// Rebuild the stream that failed, the errors of the streams dropped since are ignored.
+    fn handle_stream_error(&mut self, stream: JAudioStream, generation: usize, reason: &str) {
+        if self.engine.as_ref().map(|e| e.generation()) != Some(generation) {
+            return;
+        }
+        println!("Audio stream error: {reason}");
+        match stream {
+            // the voices live in the primary output, the next sound reopens it
+            JAudioStream::Output => self.close_engine(),
+            JAudioStream::Monitor => {
+                let monitor_device = self.state.monitor_device.clone();
+                self.set_monitor_device(monitor_device.as_deref());
+            },
+            JAudioStream::Microphone => {
+                let microphone_device = self.state.microphone_device.clone();
+                self.set_microphone_device(microphone_device.as_deref());
+            }
+        }
+    }
+
+    /// Play the mix on a second device too, none stops the monitor output.
+    pub fn set_monitor_device(&mut self, device_name: Option<&str>) {
+        self.state.monitor_device = device_name.map(String::from);
+        let device = self.get_monitor_audio_device();
+        if let Some(engine) = self.engine.as_mut() {
+            if let Err(e) = engine.set_monitor(device.as_ref()) {
+                println!("{e}");
+            }
+        }
+    }
+
+    /// Mix an input device into the primary output, none stops the passthrough.
+    pub fn set_microphone_device(&mut self, device_name: Option<&str>) {
+        self.state.microphone_device = device_name.map(String::from);
+        // a new engine starts the microphone itself
+        if self.engine.is_none() {
+            if device_name.is_some() {
+                self.open_engine();
+            }
+            return;
+        }
+        let device = self.get_microphone_audio_device();
+        if let Some(engine) = self.engine.as_mut() {
+            if let Err(e) = engine.set_microphone(device.as_ref()) {
+                println!("{e}");
+            }
+        }
+    }
+
+    pub fn set_microphone_volume(&mut self, volume: i32) {
+        self.state.microphone_volume = volume.clamp(0, MAX_VOLUME);
+        self.send_command(EngineCommand::SetMicrophoneGain(self.microphone_gain()));
+    }
+
+    pub fn set_microphone_muted(&mut self, muted: bool) {
+        self.state.microphone_muted = muted;
+        self.send_command(EngineCommand::SetMicrophoneGain(self.microphone_gain()));
+    }
+
+    fn microphone_gain(&self) -> f32 {
+        if self.state.microphone_muted {
+            return 0.0;
+        }
+        volume_to_gain(self.state.microphone_volume)
+    }
+
+    /// Duck the microphone or the sounds, none turns the ducking off.
+    pub fn set_ducking(&mut self, ducking: Option<JDucking>) {
+        self.state.ducking = ducking;
+        self.send_command(EngineCommand::SetDucking(ducking));
+    }
+
+    pub fn set_output_volume(&mut self, volume: i32) {
+        self.state.output_volume = volume.clamp(0, MAX_VOLUME);
+        self.send_command(EngineCommand::SetOutputGain(volume_to_gain(self.state.output_volume)));
+    }
+
+    pub fn set_monitor_volume(&mut self, volume: i32) {
+        self.state.monitor_volume = volume.clamp(0, MAX_VOLUME);
+        self.send_command(EngineCommand::SetMonitorGain(volume_to_gain(self.state.monitor_volume)));
+    }
+
+    /// Receive the playback events of every voice from now on.
+    pub fn playback_events(&mut self) -> Receiver<JPlaybackEvent> {
+        let (sender, receiver) = channel();
+        self.playback_senders.push(sender);
+        receiver
+    }
+
+    fn publish_voice_event(&mut self, voice_id: VoiceId, event: JVoiceEvent) {
+        let sound_id = match self.playing_voices.iter().find(|v| v.id == voice_id) {
+            Some(voice) => voice.sound_id,
+            None => return
+        };
+        let duration = self.sound_duration(sound_id).unwrap_or_default();
+        let sample_rate = self.engine.as_ref().map(|e| e.sample_rate()).unwrap_or(1);
+        let event = match event {
+            JVoiceEvent::Started => JPlaybackEvent::Started { voice_id, sound_id, duration },
+            JVoiceEvent::Progress(frames) => JPlaybackEvent::Progress {
+                voice_id,
+                sound_id,
+                position: Duration::from_secs_f64(frames as f64 / sample_rate as f64),
+                duration
+            },
+            JVoiceEvent::Finished => JPlaybackEvent::Finished { voice_id, sound_id },
+            JVoiceEvent::Stopped => JPlaybackEvent::Stopped { voice_id, sound_id }
+        };
+        if matches!(event, JPlaybackEvent::Finished { .. } | JPlaybackEvent::Stopped { .. }) {
+            self.playing_voices.retain(|v| v.id != voice_id);
+            // a sequence played until its end keeps its last stops and inputs
+            let playing = &self.playing_voices;
+            self.sequence_runs.retain(|(_, voices)| voices.iter().any(|v| playing.iter().any(|p| p.id == *v)));
+        }
+        self.playback_senders.retain(|s| s.send(event).is_ok());
+    }
+
+    pub fn event_sender(&self) -> Sender<JAppEvent> {
+        self.sender.clone()
+    }
+
+    pub fn set_volume(&mut self, volume: i32) {
+        self.state.volume = volume.clamp(0, MAX_VOLUME);
+        let gain = self.master_gain();
+        self.send_command(EngineCommand::SetMasterGain(gain));
+    }
+
+    fn master_gain(&self) -> f32 {
+        volume_to_gain(self.state.volume)
+    }
+
+    pub fn set_voice_limit(&mut self, max_voices: usize, steal_policy: JVoiceStealPolicy) {
+        self.state.max_voices = max_voices;
+        self.state.voice_steal_policy = steal_policy;
+        self.send_command(EngineCommand::SetVoiceLimit(max_voices, steal_policy));
+    }
+
+    pub fn set_master_effects(&mut self, settings: Vec<JEffect>) {
+        if let Some(engine) = self.engine.as_ref() {
+            let chain = EffectChain::new(&settings, engine.channels(), engine.sample_rate());
+            self.send_command(EngineCommand::SetMasterEffects(chain));
+        }
+        self.state.master_effects = settings;
+    }
+
+    /// Change the effects of a sound, its playing voices included.
+    pub fn set_sound_effects(&mut self, sound_id: SoundId, settings: Vec<JEffect>) {
+        let voices: Vec<VoiceId> = self.playing_voices
+            .iter()
+            .filter(|v| v.sound_id == sound_id)
+            .map(|v| v.id)
+            .collect();
+        if let Some((channels, sample_rate)) = self.engine.as_ref().map(|e| (e.channels(), e.sample_rate())) {
+            for voice_id in voices {
+                let chain = EffectChain::new(&settings, channels, sample_rate);
+                self.send_command(EngineCommand::SetVoiceEffects(voice_id, chain));
+            }
+        }
+        if let Some(entry) = self.sound_entry_mut(sound_id) {
+            entry.effects = settings;
+        }
     }
 
     pub fn with_mouse_input_on_play(&mut self, mouse_input: JMouseButton) -> &Self {
@@ -295,124 +917,509 @@ impl JApp {
         None
     }
 
-    fn get_sound_player(&self, sound_id: SoundId) -> Option<SoundDataIter> {
+    /// Settings of a loaded sound, saved with the app state.
+    pub fn sound_entry(&self, sound_id: SoundId) -> Option<&JSoundEntry> {
         let sound = self.get_sound(sound_id)?;
-        Some(SoundDataIter::new(&sound.data))
+        self.state.sounds.iter().find(|e| e.path == sound.path)
+    }
+
+    pub fn sound_entry_mut(&mut self, sound_id: SoundId) -> Option<&mut JSoundEntry> {
+        let path = self.get_sound(sound_id)?.path.clone();
+        self.state.sounds.iter_mut().find(|e| e.path == path)
+    }
+
+    /// Gain and pan of a sound from its settings.
+    fn get_sound_gain_pan(&self, sound_id: SoundId) -> (f32, f32) {
+        let (sound, entry) = match (self.get_sound(sound_id), self.sound_entry(sound_id)) {
+            (Some(sound), Some(entry)) => (sound, entry),
+            _ => return (1.0, 0.0)
+        };
+        let mut gain = db_to_gain(entry.gain_db);
+        if let Some(normalize) = entry.normalize.as_ref() {
+            gain *= sound.levels.normalize_gain(normalize);
+        }
+        (gain, entry.pan)
+    }
+
+    fn get_sound_fades(&self, sound_id: SoundId, sample_rate: u32) -> VoiceFades {
+        let entry = self.sound_entry(sound_id);
+        let fade_in_ms = entry.and_then(|e| e.fade_in_ms).unwrap_or(self.state.fade_in_ms);
+        let fade_out_ms = entry.and_then(|e| e.fade_out_ms).unwrap_or(self.state.fade_out_ms);
+        let to_frames = |ms: f32| (ms.max(0.0) * sample_rate as f32 / 1000.0) as usize;
+        VoiceFades {
+            fade_in: to_frames(fade_in_ms),
+            fade_out: to_frames(fade_out_ms)
+        }
+    }
+
+    /// Reader of a sound following its loop points, `looping` loops it until stopped.
+    fn get_sound_player(&self, sound_id: SoundId, looping: bool) -> Option<(SoundDataIter, u32)> {
+        let sound = self.get_sound(sound_id)?;
+        let channels = sound.channels as usize;
+        let frames = sound.data.len() / channels.max(1);
+        let loop_points = self.sound_entry(sound_id).and_then(|e| e.loop_points);
+        let loop_region = loop_points.and_then(|p| LoopRegion::new(&p, frames, sound.sample_rate));
+        let (loop_region, loop_count) = match (loop_region, looping) {
+            (Some(region), false) => (Some(region), loop_points.and_then(|p| p.count)),
+            (Some(region), true) => (Some(region), None),
+            (None, true) => (LoopRegion::whole(frames), None),
+            (None, false) => (None, None)
+        };
+        Some((SoundDataIter::new(sound.data.clone(), channels, loop_region, loop_count), sound.sample_rate))
+    }
+
+    pub fn play(&mut self, sound_id: SoundId) -> Option<VoiceId> {
+        self.play_sound(sound_id, false, JPlayback::default())
+    }
+
+    /// Play a sound in loop until it is stopped.
+    pub fn play_loop(&mut self, sound_id: SoundId) -> Option<VoiceId> {
+        self.play_sound(sound_id, true, JPlayback::default())
+    }
+
+    /// Play a sound faster, slower or shifted on top of its own settings.
+    pub fn play_with(&mut self, sound_id: SoundId, playback: JPlayback) -> Option<VoiceId> {
+        self.play_sound(sound_id, false, playback)
+    }
+
+    fn play_sound(&mut self, sound_id: SoundId, looping: bool, playback: JPlayback) -> Option<VoiceId> {
+        let voice = self.new_voice(sound_id, looping, 0.0, playback)?;
+        let playing = PlayingVoice::new(&voice);
+        let command = EngineCommand::AddVoice {
+            id: voice.id,
+            source: voice.source,
+            fades: voice.fades,
+            choke_group: voice.choke_group
+        };
+        if !self.send_command(command) {
+            return None;
+        }
+        self.playing_voices.push(playing);
+        self.press_inputs();
+        Some(voice.id)
+    }
+
+    /// Build the voice of a sound for the output stream, opening it if needed.
+    fn new_voice(&mut self, sound_id: SoundId, looping: bool, gain_db: f32, playback: JPlayback) -> Option<NewVoice> {
+        let (sound_player, sound_rate) = self.get_sound_player(sound_id, looping)?;
+        let (gain, pan) = self.get_sound_gain_pan(sound_id);
+        let choke_group = self.sound_entry(sound_id).and_then(|e| e.choke_group);
+        let playback = self.sound_entry(sound_id).map(|e| e.playback).unwrap_or_default().then(&playback).clamped();
+        let quality = self.state.resample_quality;
+        let id = VoiceId(self.voice_idx_count);
+        self.voice_idx_count += 1;
+
+        let (channels, sample_rate) = self.open_engine().map(|e| (e.channels(), e.sample_rate()))?;
+        let fades = self.get_sound_fades(sound_id, sample_rate);
+        let source = build_sound_source(
+            sound_player,
+            sound_rate,
+            channels,
+            sample_rate,
+            quality,
+            playback
+        );
+        let source = GainPan::new(source, gain * db_to_gain(gain_db), pan);
+        // always there so effects can be added while the sound plays
+        let effects = self.sound_entry(sound_id).map(|e| e.effects.clone()).unwrap_or_default();
+        let source = Box::new(EffectSource::new(source, &effects, sample_rate));
+        Some(NewVoice { id, sound_id, playback, source, fades, choke_group })
+    }
+
+    /// Schedule every step of a sequence, return the voices it will play.
+    pub fn play_sequence(&mut self, sequence_id: SequenceId) -> Vec<VoiceId> {
+        let timeline = match self.sequences.iter().find(|(id, _)| *id == sequence_id) {
+            Some((_, sequence)) => sequence.timeline(),
+            None => return Vec::new()
+        };
+        let sample_rate = match self.open_engine() {
+            Some(engine) => engine.sample_rate(),
+            None => return Vec::new()
+        };
+        let to_frames = |ms: f32| (ms as f64 * sample_rate as f64 / 1000.0).round() as usize;
+        // the run owns the stops and inputs of the sequence, they are cancelled with its voices
+        let run = SequenceRunId(self.sequence_run_idx_count);
+        self.sequence_run_idx_count += 1;
+
+        // build every voice before queuing them so the whole sequence starts on the same frame
+        let now = std::time::Instant::now();
+        let mut steps = Vec::with_capacity(timeline.len());
+        for (time, step) in timeline {
+            match step {
+                JSequenceStep::Play(sound_id, gain_db) => {
+                    if let Some(voice) = self.new_voice(sound_id, false, gain_db, JPlayback::default()) {
+                        steps.push((to_frames(time), Some(voice)));
+                    }
+                },
+                JSequenceStep::StopAll => steps.push((to_frames(time), None)),
+                JSequenceStep::Input(button) => {
+                    let delay = std::time::Duration::from_secs_f32(time / 1000.0);
+                    self.scheduled_inputs.push((now + delay, button, run));
+                },
+                JSequenceStep::Wait(_) => ()
+            }
+        }
+
+        let mut voices = Vec::new();
+        for (delay, voice) in steps {
+            match voice {
+                Some(voice) => {
+                    let playing = PlayingVoice::new(&voice);
+                    let command = EngineCommand::ScheduleVoice {
+                        delay,
+                        id: voice.id,
+                        source: voice.source,
+                        fades: voice.fades,
+                        choke_group: voice.choke_group
+                    };
+                    if self.send_command(command) {
+                        voices.push(playing.id);
+                        self.playing_voices.push(playing);
+                    }
+                },
+                None => {
+                    self.send_command(EngineCommand::ScheduleStopAll(delay, run));
+                }
+            }
+        }
+        if !voices.is_empty() {
+            self.sequence_runs.push((run, voices.clone()));
+            self.press_inputs();
+        }
+        voices
+    }
+
+    /// Cancel what is left of a sequence once every voice it played is stopped or over.
+    fn cancel_sequence_run(&mut self, voice_id: VoiceId) {
+        let index = match self.sequence_runs.iter().position(|(_, voices)| voices.contains(&voice_id)) {
+            Some(index) => index,
+            None => return
+        };
+        if self.sequence_runs[index].1.iter().any(|v| self.is_voice_active(*v)) {
+            return;
+        }
+        let (run, _) = self.sequence_runs.remove(index);
+        self.scheduled_inputs.retain(|(_, _, owner)| *owner != run);
+        self.send_command(EngineCommand::CancelSequenceRun(run));
+    }
+
+    fn run_scheduled_inputs(&mut self) {
+        let now = std::time::Instant::now();
+        let (due, waiting): (Vec<_>, Vec<_>) = self.scheduled_inputs.drain(..).partition(|(time, _, _)| *time <= now);
+        self.scheduled_inputs = waiting;
+        let inputs = due
+            .iter()
+            .flat_map(|(_, button, _)| [button.get_press_input(), button.get_release_input()])
+            .collect::<Vec<INPUT>>();
+        if !inputs.is_empty() {
+            unsafe {
+                SendInput(inputs.len() as _, inputs.as_ptr(), size_of::<INPUT>() as _);
+            }
+        }
+    }
+
+    /// Fade out every sound and cancel the sequences, the simulated inputs are released once the mixer is silent.
+    pub fn stop(&mut self) {
+        self.scheduled_inputs.clear();
+        self.sequence_runs.clear();
+        self.playing_voices.iter_mut().for_each(|v| v.stopping = true);
+        self.send_command(EngineCommand::StopAll);
+        if !self.is_playing() {
+            self.release_inputs();
+        }
     }
 
-    pub fn play(&mut self, sound_id: SoundId) {
-        self.stop();
+    pub fn stop_voice(&mut self, voice_id: VoiceId) {
+        if let Some(voice) = self.playing_voices.iter_mut().find(|v| v.id == voice_id) {
+            voice.stopping = true;
+        }
+        self.send_command(EngineCommand::StopVoice(voice_id));
+        self.cancel_sequence_run(voice_id);
+        if !self.is_playing() {
+            self.release_inputs();
+        }
+    }
 
-        let sound_player = match self.get_sound_player(sound_id) {
-            Some(s) => s,
-            _ => return
+    pub fn pause_voice(&mut self, voice_id: VoiceId) {
+        if let Some(voice) = self.playing_voices.iter_mut().find(|v| v.id == voice_id) {
+            voice.paused = true;
+        }
+        self.send_command(EngineCommand::PauseVoice(voice_id));
+    }
+
+    pub fn resume_voice(&mut self, voice_id: VoiceId) {
+        if let Some(voice) = self.playing_voices.iter_mut().find(|v| v.id == voice_id) {
+            voice.paused = false;
+        }
+        self.send_command(EngineCommand::ResumeVoice(voice_id));
+    }
+
+    pub fn is_voice_paused(&self, voice_id: VoiceId) -> bool {
+        self.playing_voices.iter().any(|v| v.id == voice_id && v.paused)
+    }
+
+    /// Move a voice to a position of its sound.
+    pub fn seek_voice(&mut self, voice_id: VoiceId, position: Duration) {
+        let sound_rate = match self.playing_voices.iter().find(|v| v.id == voice_id) {
+            Some(voice) => self.get_sound(voice.sound_id).map(|s| s.sample_rate),
+            None => None
         };
+        if let (Some(sound_rate), Some(engine)) = (sound_rate, self.engine.as_ref()) {
+            let frame = (position.as_secs_f64() * sound_rate as f64) as usize;
+            let played = (position.as_secs_f64() * engine.sample_rate() as f64) as usize;
+            self.send_command(EngineCommand::SeekVoice { id: voice_id, frame, played });
+        }
+    }
+
+    /// Pause every voice, or resume them all if they are all paused already.
+    pub fn toggle_pause(&mut self) {
+        let voices = self.playing_voice_ids();
+        let pause = voices.iter().any(|v| !self.is_voice_paused(*v));
+        for voice_id in voices {
+            if pause {
+                self.pause_voice(voice_id);
+            } else {
+                self.resume_voice(voice_id);
+            }
+        }
+    }
 
+    fn playing_voice_ids(&self) -> Vec<VoiceId> {
+        self.playing_voices.iter().map(|v| v.id).collect()
+    }
+
+    /// Change the speed and pitch of a playing voice.
+    pub fn set_voice_playback(&mut self, voice_id: VoiceId, playback: JPlayback) {
+        // stored as played so the positions follow the real speed
+        let playback = playback.clamped();
+        if let Some(voice) = self.playing_voices.iter_mut().find(|v| v.id == voice_id) {
+            voice.playback = playback;
+        }
+        self.send_command(EngineCommand::SetVoicePlayback(voice_id, playback));
+    }
+
+    pub fn voice_playback(&self, voice_id: VoiceId) -> Option<JPlayback> {
+        self.playing_voices.iter().find(|v| v.id == voice_id).map(|v| v.playback)
+    }
+
+    /// Change the speed and pitch of every voice, `None` brings a voice back to how it started.
+    fn change_playback<F: Fn(&JPlayback) -> Option<JPlayback>>(&mut self, change: F) {
+        let changes: Vec<(VoiceId, JPlayback)> = self.playing_voices
+            .iter()
+            .map(|v| (v.id, change(&v.playback).unwrap_or(v.initial_playback)))
+            .collect();
+        for (voice_id, playback) in changes {
+            self.set_voice_playback(voice_id, playback);
+        }
+    }
+
+    /// Speed and pitch of the sounds played by a hotkey, on top of the sound settings.
+    pub fn set_hotkey_playback(&mut self, hotkey: &str, playback: JPlayback) -> Result<(), String> {
+        let hotkey = JGlobalHotkey::try_from(hotkey).map_err(|_| format!("Invalid hotkey {hotkey}"))?;
+        match self.hotkey_sound_mapping.iter_mut().find(|b| b.hotkey == hotkey) {
+            Some(binding) => {
+                binding.playback = playback;
+                Ok(())
+            },
+            None => Err(String::from("Hotkey not registered"))
+        }
+    }
+
+    /// A voice is active from the moment it is played until it ends or is stopped.
+    pub fn is_voice_active(&self, voice_id: VoiceId) -> bool {
+        self.playing_voices.iter().any(|v| v.id == voice_id && !v.stopping)
+    }
+
+    fn trigger_binding(&mut self, index: usize) {
+        if let BindingTarget::Transport(action) = self.hotkey_sound_mapping[index].target {
+            match action {
+                JTransportAction::TogglePause => self.toggle_pause(),
+                JTransportAction::SeekTo(ms) => {
+                    let position = Duration::from_secs_f32(ms.max(0.0) / 1000.0);
+                    self.playing_voice_ids().into_iter().for_each(|v| self.seek_voice(v, position));
+                },
+                JTransportAction::ChangeRate(factor) => self.change_playback(|p| Some(JPlayback { rate: p.rate * factor, ..*p })),
+                JTransportAction::ChangePitch(semitones) => self.change_playback(|p| Some(JPlayback { pitch: p.pitch + semitones, ..*p })),
+                JTransportAction::ResetPlayback => self.change_playback(|_| None)
+            }
+            return;
+        }
+        if let BindingTarget::Recording = self.hotkey_sound_mapping[index].target {
+            // a hold hotkey can't stop a recording it didn't start
+            if self.hotkey_sound_mapping[index].mode != JTriggerMode::Hold || !self.is_recording() {
+                self.toggle_recording();
+            }
+            return;
+        }
+
+        let mode = self.hotkey_sound_mapping[index].mode;
+        let mut voices: Vec<VoiceId> = self.hotkey_sound_mapping[index].voices
+            .iter()
+            .copied()
+            .filter(|v| self.is_voice_active(*v))
+            .collect();
+
+        // toggle and loop stop the sound instead of playing a new one
+        let stop_only = matches!(mode, JTriggerMode::Toggle | JTriggerMode::Loop) && !voices.is_empty();
+        if stop_only || mode == JTriggerMode::Retrigger {
+            voices.drain(..).for_each(|v| self.stop_voice(v));
+        }
+        if !stop_only {
+            match self.hotkey_sound_mapping[index].target {
+                BindingTarget::Sounds(ref mut pool) => {
+                    if let Some(sound_id) = pool.next_sound() {
+                        let playback = self.hotkey_sound_mapping[index].playback;
+                        voices.extend(self.play_sound(sound_id, mode == JTriggerMode::Loop, playback));
+                    }
+                },
+                BindingTarget::Sequence(sequence_id) => voices.extend(self.play_sequence(sequence_id)),
+                BindingTarget::Transport(_) | BindingTarget::Recording => ()
+            }
+        }
+        self.hotkey_sound_mapping[index].voices = voices;
+    }
+
+    fn release_binding(&mut self, index: usize) {
+        if self.hotkey_sound_mapping[index].mode != JTriggerMode::Hold {
+            return;
+        }
+        if let BindingTarget::Recording = self.hotkey_sound_mapping[index].target {
+            if self.is_recording() {
+                self.toggle_recording();
+            }
+            return;
+        }
+        let voices = std::mem::take(&mut self.hotkey_sound_mapping[index].voices);
+        voices.into_iter().for_each(|v| self.stop_voice(v));
+    }
+
+    /// True until the last voice is silent, fade outs included.
+    pub fn is_playing(&self) -> bool {
+        !self.playing_voices.is_empty()
+    }
+
+    fn press_inputs(&mut self) {
+        if self.inputs_pressed {
+            return;
+        }
         let inputs = self.simulate_key_press_on_play
             .iter()
             .map(|i| i.get_press_input())
             .collect::<Vec<INPUT>>();
-        if inputs.len() > 0 {
+        if !inputs.is_empty() {
             unsafe {
                 SendInput(inputs.len() as _, inputs.as_ptr(), size_of::<INPUT>() as _);
             }
         };
-        self.play_sound(sound_player);
+        self.inputs_pressed = true;
     }
 
-    pub fn stop(&mut self) {
-        if let Some(_) = self.sound_stream.take() {
-            // dropping the stream should stop it.
-            let inputs = self.simulate_key_press_on_play
+    fn release_inputs(&mut self) {
+        if !self.inputs_pressed {
+            return;
+        }
+        let inputs = self.simulate_key_press_on_play
             .iter()
             .map(|i| i.get_release_input())
             .collect::<Vec<INPUT>>();
-            if inputs.len() > 0 {
-                unsafe {
-                    SendInput(inputs.len() as _, inputs.as_ptr(), size_of::<INPUT>() as _);
-                }
-            };
+        if !inputs.is_empty() {
+            unsafe {
+                SendInput(inputs.len() as _, inputs.as_ptr(), size_of::<INPUT>() as _);
+            }
         };
+        self.inputs_pressed = false;
     }
 
-    // #[allow(dead_code)] 
-    // fn play_sound(&self) {
-    //     if let Some(device) = self.get_output_audio_device(){
-    //         println!("Using output device: {}", device.name().unwrap());
-    //         let config = device.default_output_config().unwrap();
-    //         println!("Sample format: {:?}", config.sample_format());
-    //         println!("Sample rate: {:?}", config.sample_rate());
-    //         println!("Channel: {:?}", config.channels());
-    //         std::thread::spawn(move || {
-    //             // see cpal example for more format info
-    //             match config.sample_format() {
-    //                 cpal::SampleFormat::F32 => play_sound_open::<f32>(&device, &config.into()),
-    //                 sample_format => panic!("Unsupported sample format '{sample_format}'"),
-    //             };
-    //         });
-    //     } else {
-    //         println!("Error couldn't find audio device.");
-    //     }
-    // }
-
-    fn play_sound(&mut self, mut sound_player: SoundDataIter) {
-        if let Some(device) = self.get_output_audio_device(){
-            let config = device.default_output_config().unwrap();
-            // maybe make sure that this is two channels ?
-            // let channels = config.channels() as usize;
-
-            // TODO: fix unwrap
-            match config.sample_format() {
-                cpal::SampleFormat::F32 => {
-                    let stop_sender = self.sender.clone();
-                    match device.build_output_stream(
-                        &config.into(),
-                        move |output: &mut[f32], _info| {
-                            // iterator over [0, 1 ... nChannel] blocks
-                            for sample in output.iter_mut() {
-                                // set the value of each channel
-                                *sample = sound_player.next_value();
-                            }
-                            if sound_player.is_done() {
-                                let _ = stop_sender.send(JAppEvent::StopAudio);
-                            }
-                        },
-                        |err| eprintln!("an error occurred on stream: {}", err),
-                        None
-                    ) {
-                        Ok(stream) => {
-                            if let Ok(_) = stream.play() {
-                                self.sound_stream = Some(Rc::new(stream));
-                            }
-                        },
-                        Err(e) => {
-                            println!("Error building stream {e} :(");
-                        }
-                    };
-                },
-                sample_format => panic!("Unsupported sample format '{sample_format}'"),
-            };
+    /// Start the audio engine once, every sound is then mixed in the same stream.
+    fn open_engine(&mut self) -> Option<&AudioEngine> {
+        if self.engine.is_none() {
+            let device = self.get_output_audio_device()?;
+            let master_gain = self.master_gain();
+            let master_effects = &self.state.master_effects;
+            let (max_voices, steal_policy) = (self.state.max_voices, self.state.voice_steal_policy);
+            self.engine_generation += 1;
+            let engine = AudioEngine::new(&device, self.sender.clone(), self.engine_generation, |channels, sample_rate| {
+                let mut mixer = Mixer::new(channels, sample_rate, max_voices, steal_policy);
+                mixer.set_master_gain(master_gain);
+                mixer.set_master_effects(EffectChain::new(master_effects, channels, sample_rate));
+                mixer
+            });
+            match engine {
+                Ok(engine) => self.engine = Some(engine),
+                Err(e) => {
+                    println!("{e}");
+                    return None;
+                }
+            }
+            self.set_output_volume(self.state.output_volume);
+            self.set_monitor_volume(self.state.monitor_volume);
+            self.set_microphone_volume(self.state.microphone_volume);
+            self.set_ducking(self.state.ducking);
+            if self.state.monitor_device.is_some() {
+                let monitor_device = self.state.monitor_device.clone();
+                self.set_monitor_device(monitor_device.as_deref());
+            }
+            if self.state.microphone_device.is_some() {
+                let microphone_device = self.state.microphone_device.clone();
+                self.set_microphone_device(microphone_device.as_deref());
+            }
+        }
+        self.engine.as_ref()
+    }
+
+    /// Queue a command for the audio thread, nothing to do before the engine is started.
+    fn send_command(&mut self, command: EngineCommand) -> bool {
+        match self.engine.as_mut() {
+            Some(engine) => {
+                let sent = engine.send(command);
+                if !sent {
+                    println!("Audio engine command queue is full");
+                }
+                sent
+            },
+            None => false
         }
     }
 
+    /// Follow the voices through the events of the audio thread, false when there was none.
+    fn process_voice_events(&mut self) -> bool {
+        let mut processed = false;
+        while let Some((voice_id, event)) = self.engine.as_mut().and_then(|e| e.next_event()) {
+            self.publish_voice_event(voice_id, event);
+            processed = true;
+        }
+        // the inputs are held while any voice plays
+        if processed && !self.is_playing() {
+            self.release_inputs();
+        }
+        processed
+    }
+
     pub fn process_events(&mut self) -> bool {
+        if !self.scheduled_inputs.is_empty() {
+            self.run_scheduled_inputs();
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.drain();
+        }
+        if let Some(engine) = self.engine.as_mut() {
+            engine.collect_garbage();
+        }
+        if self.process_voice_events() {
+            return true;
+        }
+
         match JGlobalHotkeyManager::event() {
             JGlobalHotKeyEvent::HotkeyPressed(ref hotkey) => {
-                for (shk, sound_id) in self.hotkey_sound_mapping.iter() {
-                    if shk == hotkey {
-                        self.play(*sound_id);
-                        return true;
-                    }
+                if let Some(index) = self.hotkey_sound_mapping.iter().position(|b| b.hotkey == *hotkey) {
+                    self.trigger_binding(index);
+                    return true;
                 }
             },
             JGlobalHotKeyEvent::HotkeyReleased(ref hotkey) => {
-                if self.state.stop_audio_on_release {
-                    for (shk, _) in self.hotkey_sound_mapping.iter() {
-                        if shk == hotkey {
-                            self.stop();
-                            return true;
-                        }
-                    }
+                if let Some(index) = self.hotkey_sound_mapping.iter().position(|b| b.hotkey == *hotkey) {
+                    self.release_binding(index);
+                    return true;
                 }
             }
             _ => ()
@@ -421,10 +1428,25 @@ impl JApp {
         loop {
             if let Ok(event) = self.receiver.try_recv() {
                 match event {
-                    JAppEvent::StopAudio => {
-                        self.stop();
+                    JAppEvent::SetVolume(volume) => {
+                        self.set_volume(volume);
                         return true;
-                    }
+                    },
+                    JAppEvent::LoudnessAnalyzed(sound_id, loudness) => {
+                        self.set_sound_loudness(sound_id, loudness);
+                        return true;
+                    },
+                    JAppEvent::AudioStreamError { stream, generation, reason } => {
+                        self.handle_stream_error(stream, generation, &reason);
+                        return true;
+                    },
+                    JAppEvent::RecordingError(reason) => {
+                        println!("Recording stream error: {reason}");
+                        if self.is_recording() {
+                            self.toggle_recording();
+                        }
+                        return true;
+                    },
                 }
             } else {
                 return false;
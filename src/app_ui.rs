@@ -1,5 +1,6 @@
 
 use std::rc::Rc;
+use std::sync::mpsc::Sender;
 
 use slint::{ModelRc, SharedString, VecModel};
 
@@ -9,16 +10,20 @@ pub mod ui {
 
 use ui::*;
 
+use crate::JAppEvent;
+
 pub struct JAppUI {
-    ui: Option<JAppUISlint>
+    ui: Option<JAppUISlint>,
+    event_sender: Sender<JAppEvent>
 }
 
 
 impl JAppUI {
 
-    pub fn new() -> Self{
+    pub fn new(event_sender: Sender<JAppEvent>) -> Self{
         JAppUI {
-            ui: None
+            ui: None,
+            event_sender
         }
     }
 
@@ -29,6 +34,10 @@ impl JAppUI {
         }
         // init the app.  asign it and show it.
         let app = JAppUISlint::new().unwrap();
+        let sender = self.event_sender.clone();
+        app.on_volume_changed(move |volume| {
+            let _ = sender.send(JAppEvent::SetVolume(volume));
+        });
         app.show().unwrap();
         self.ui = Some(app);
         return true;
@@ -48,4 +57,10 @@ impl JAppUI {
             app.set_current_output_device(device.into());
         }
     }
-}
+
+    pub fn set_volume(&self, volume: i32) {
+        if let Some(app) = self.ui.as_ref() {
+            app.set_volume(volume);
+        }
+    }
+}
\ No newline at end of file
@@ -0,0 +1,164 @@
+use rtrb::{Consumer, Producer, RingBuffer};
+
+use crate::source::JFrameSource;
+
+
+// audio kept between two devices, enough for a block of each
+const BRIDGE_LATENCY_SECONDS: f64 = 0.05;
+// the buffer holds this many times the latency before dropping blocks
+const BRIDGE_BUFFER_LATENCIES: usize = 4;
+// time over which the buffer level is averaged before correcting the speed
+const FILL_AVERAGE_SECONDS: f64 = 1.0;
+// speed correction for a buffer holding twice the latency
+const CORRECTION_GAIN: f64 = 0.01;
+// clock drift is far smaller than that, and it stays inaudible
+const MAX_CORRECTION: f64 = 0.005;
+
+
+/// Buffer carrying audio between two devices running on their own clocks.
+pub fn bridge_buffer(channels: usize, sample_rate: u32) -> (Producer<f32>, Consumer<f32>) {
+    let latency = (sample_rate as f64 * BRIDGE_LATENCY_SECONDS) as usize;
+    RingBuffer::new(latency.max(1) * BRIDGE_BUFFER_LATENCIES * channels.max(1))
+}
+
+
+/// Write a block for the other device, it is dropped when the reader can't keep up.
+pub fn send_block(producer: &mut Producer<f32>, block: &[f32]) {
+    // only whole blocks so the channels stay aligned
+    if producer.slots() < block.len() {
+        return;
+    }
+    for sample in block {
+        let _ = producer.push(*sample);
+    }
+}
+
+
+/// Read a bridge buffer at the sample rate of another device, speeding up or slowing down
+/// a little to keep the buffer level constant when the two device clocks drift.
+pub struct BridgeReader {
+    consumer: Consumer<f32>,
+    channels: usize,
+    // frames read for each frame played without drift
+    nominal_ratio: f64,
+    target_fill: f64,
+    fill: f64,
+    fill_alpha: f64,
+    position: f64,
+    previous: Vec<f32>,
+    next: Vec<f32>,
+    // waiting for the buffer to reach its level, at the start and after an underrun
+    priming: bool
+}
+
+impl BridgeReader {
+
+    pub fn new(consumer: Consumer<f32>, channels: usize, input_rate: u32, output_rate: u32) -> Self {
+        let target_fill = (input_rate as f64 * BRIDGE_LATENCY_SECONDS).max(1.0);
+        BridgeReader {
+            consumer,
+            channels,
+            nominal_ratio: input_rate as f64 / output_rate.max(1) as f64,
+            target_fill,
+            fill: target_fill,
+            fill_alpha: 1.0 / (output_rate as f64 * FILL_AVERAGE_SECONDS).max(1.0),
+            position: 0.0,
+            previous: vec![0.0; channels],
+            next: vec![0.0; channels],
+            priming: true
+        }
+    }
+
+    /// Whole frames waiting in the buffer.
+    fn buffered(&self) -> usize {
+        self.consumer.slots() / self.channels
+    }
+
+    fn pop_frame(&mut self) -> bool {
+        if self.buffered() == 0 {
+            return false;
+        }
+        std::mem::swap(&mut self.previous, &mut self.next);
+        for sample in self.next.iter_mut() {
+            *sample = self.consumer.pop().unwrap_or(0.0);
+        }
+        true
+    }
+
+    /// Frames read for each frame played, corrected for the drift.
+    fn ratio(&self) -> f64 {
+        let error = (self.fill - self.target_fill) / self.target_fill;
+        // read faster when the buffer grows, slower when it empties
+        self.nominal_ratio * (1.0 + (error * CORRECTION_GAIN).clamp(-MAX_CORRECTION, MAX_CORRECTION))
+    }
+}
+
+impl JFrameSource for BridgeReader {
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn next_frame(&mut self, frame: &mut [f32]) -> bool {
+        let buffered = self.buffered() as f64;
+        if self.priming {
+            if buffered < self.target_fill {
+                frame.fill(0.0);
+                return true;
+            }
+            self.priming = false;
+            self.fill = buffered;
+            self.position = 0.0;
+            self.pop_frame();
+            self.pop_frame();
+        }
+        self.fill += (buffered - self.fill) * self.fill_alpha;
+
+        let t = self.position as f32;
+        for ((sample, previous), next) in frame.iter_mut().zip(self.previous.iter()).zip(self.next.iter()) {
+            *sample = previous + (next - previous) * t;
+        }
+        self.position += self.ratio();
+        while self.position >= 1.0 {
+            self.position -= 1.0;
+            if !self.pop_frame() {
+                self.priming = true;
+                break;
+            }
+        }
+        // the bridge plays until it is removed
+        true
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{bridge_buffer, send_block, BridgeReader};
+    use crate::source::JFrameSource;
+
+    #[test]
+    fn drift_test() {
+        // the primary clock runs 0.1% fast, without correction the buffer overflows within a minute
+        let (mut producer, consumer) = bridge_buffer(1, 48000);
+        let mut reader = BridgeReader::new(consumer, 1, 48000, 44100);
+        let mut written = 0.0;
+        let mut frame = [0.0];
+        let mut levels = Vec::new();
+        for tick in 0..6000 {
+            let block = (48048.0 * (tick + 1) as f64 / 100.0) as usize - written as usize;
+            written += block as f64;
+            send_block(&mut producer, &vec![0.5; block]);
+            for _ in 0..441 {
+                assert!(reader.next_frame(&mut frame));
+            }
+            if tick >= 100 {
+                assert!(!reader.priming, "underrun at {tick}");
+                levels.push(reader.buffered());
+            }
+        }
+        assert_eq!(frame[0], 0.5);
+        let level = levels[levels.len() - 1000..].iter().sum::<usize>() as f64 / 1000.0;
+        assert!((level - 2400.0).abs() < 600.0, "{level}");
+    }
+}
@@ -0,0 +1,154 @@
+use crate::pitch::JPlayback;
+use crate::source::JFrameSource;
+
+
+const MINUS_3DB: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+
+/// Gain of each input channel on the left and right output channel, assuming
+/// the usual wav / vorbis channel order (FL, FR, FC, LFE, BL, BR, SL, SR).
+fn stereo_gains(channels: usize) -> Vec<(f32, f32)> {
+    let layout: &[(f32, f32)] = match channels {
+        1 => &[(1.0, 1.0)],
+        2 => &[(1.0, 0.0), (0.0, 1.0)],
+        3 => &[(1.0, 0.0), (0.0, 1.0), (MINUS_3DB, MINUS_3DB)],
+        4 => &[(1.0, 0.0), (0.0, 1.0), (MINUS_3DB, 0.0), (0.0, MINUS_3DB)],
+        5 => &[(1.0, 0.0), (0.0, 1.0), (MINUS_3DB, MINUS_3DB), (MINUS_3DB, 0.0), (0.0, MINUS_3DB)],
+        6 => &[(1.0, 0.0), (0.0, 1.0), (MINUS_3DB, MINUS_3DB), (0.0, 0.0), (MINUS_3DB, 0.0), (0.0, MINUS_3DB)],
+        8 => &[
+            (1.0, 0.0), (0.0, 1.0), (MINUS_3DB, MINUS_3DB), (0.0, 0.0),
+            (MINUS_3DB, 0.0), (0.0, MINUS_3DB), (MINUS_3DB, 0.0), (0.0, MINUS_3DB)
+        ],
+        // unknown layout, alternate between left and right
+        _ => return (0..channels)
+            .map(|c| if c % 2 == 0 { (MINUS_3DB, 0.0) } else { (0.0, MINUS_3DB) })
+            .collect()
+    };
+    layout.to_vec()
+}
+
+
+/// Build the `output x input` gain matrix used to map `input` channels on `output` channels.
+pub fn mixing_matrix(input: usize, output: usize) -> Vec<Vec<f32>> {
+    let mut matrix = vec![vec![0.0; input]; output];
+    if input == output {
+        for (c, row) in matrix.iter_mut().enumerate() {
+            row[c] = 1.0;
+        }
+    } else if input == 1 {
+        // mono goes in the front left and right speakers
+        for row in matrix.iter_mut().take(2) {
+            row[0] = 1.0;
+        }
+    } else if output <= 2 {
+        for (c, (left, right)) in stereo_gains(input).into_iter().enumerate() {
+            if output == 1 {
+                matrix[0][c] = (left + right) * 0.5;
+            } else {
+                matrix[0][c] = left;
+                matrix[1][c] = right;
+            }
+        }
+    } else {
+        // copy the channels both layouts share and fold the extra ones in the front speakers
+        for (c, (left, right)) in stereo_gains(input).into_iter().enumerate() {
+            if c < output {
+                matrix[c][c] = 1.0;
+            } else {
+                matrix[0][c] = left;
+                matrix[1][c] = right;
+            }
+        }
+    }
+
+    // keep a full scale input from clipping the output
+    for row in matrix.iter_mut() {
+        let sum: f32 = row.iter().sum();
+        if sum > 1.0 {
+            row.iter_mut().for_each(|g| *g /= sum);
+        }
+    }
+    matrix
+}
+
+
+/// Up or down mix a source to another channel count.
+pub struct ChannelMapper<S: JFrameSource> {
+    source: S,
+    matrix: Vec<Vec<f32>>,
+    input: Vec<f32>
+}
+
+impl<S: JFrameSource> ChannelMapper<S> {
+
+    pub fn new(source: S, channels: usize) -> Self {
+        let input = source.channels();
+        ChannelMapper {
+            source,
+            matrix: mixing_matrix(input, channels),
+            input: vec![0.0; input]
+        }
+    }
+}
+
+impl<S: JFrameSource> JFrameSource for ChannelMapper<S> {
+
+    fn channels(&self) -> usize {
+        self.matrix.len()
+    }
+
+    fn next_frame(&mut self, frame: &mut [f32]) -> bool {
+        if !self.source.next_frame(&mut self.input) {
+            return false;
+        }
+        for (sample, gains) in frame.iter_mut().zip(self.matrix.iter()) {
+            *sample = gains.iter().zip(self.input.iter()).map(|(g, s)| g * s).sum();
+        }
+        true
+    }
+
+    fn seek(&mut self, frame: usize) -> bool {
+        self.source.seek(frame)
+    }
+
+    fn position(&self) -> Option<usize> {
+        self.source.position()
+    }
+
+    fn set_playback(&mut self, playback: JPlayback) -> bool {
+        self.source.set_playback(playback)
+    }
+
+    fn playback_rate(&self) -> f64 {
+        self.source.playback_rate()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::mixing_matrix;
+
+    #[test]
+    fn mono_stereo_test() {
+        assert_eq!(mixing_matrix(1, 2), vec![vec![1.0], vec![1.0]]);
+        assert_eq!(mixing_matrix(2, 1), vec![vec![0.5, 0.5]]);
+        assert_eq!(mixing_matrix(1, 4), vec![vec![1.0], vec![1.0], vec![0.0], vec![0.0]]);
+        assert_eq!(mixing_matrix(2, 4), vec![
+            vec![1.0, 0.0], vec![0.0, 1.0], vec![0.0, 0.0], vec![0.0, 0.0]
+        ]);
+    }
+
+    #[test]
+    fn surround_downmix_test() {
+        let matrix = mixing_matrix(6, 2);
+        // lfe is dropped and nothing can clip
+        assert_eq!(matrix[0][3], 0.0);
+        assert_eq!(matrix[1][3], 0.0);
+        for row in matrix.iter() {
+            assert!(row.iter().sum::<f32>() <= 1.0 + f32::EPSILON);
+        }
+        assert!(matrix[0][0] > matrix[0][2]);
+        assert_eq!(matrix[0][1], 0.0);
+    }
+}
@@ -0,0 +1,328 @@
+use std::io::{Read, Seek, SeekFrom};
+
+use symphonia::core::{
+    audio::SampleBuffer,
+    codecs::{DecoderOptions, CODEC_TYPE_NULL},
+    errors::Error as SymphoniaError,
+    formats::FormatOptions,
+    io::{MediaSource, MediaSourceStream},
+    meta::MetadataOptions,
+    probe::Hint
+};
+
+
+#[derive(Debug)]
+pub enum JSoundErrors {
+    Io(std::io::Error),
+    UnsupportedFormat(String),
+    Decode(String),
+    Empty
+}
+
+impl std::fmt::Display for JSoundErrors {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JSoundErrors::Io(e) => write!(f, "Couldn't read sound file: {e}"),
+            JSoundErrors::UnsupportedFormat(reason) => write!(f, "Unsupported sound format: {reason}"),
+            JSoundErrors::Decode(reason) => write!(f, "Couldn't decode sound file: {reason}"),
+            JSoundErrors::Empty => write!(f, "Sound file doesn't contain any sample")
+        }
+    }
+}
+
+impl From<std::io::Error> for JSoundErrors {
+
+    fn from(value: std::io::Error) -> Self {
+        match value.kind() {
+            // the wav crate report unsupported bit depth and encoding as `Other`.
+            std::io::ErrorKind::Other => JSoundErrors::UnsupportedFormat(value.to_string()),
+            _ => JSoundErrors::Io(value)
+        }
+    }
+}
+
+impl From<SymphoniaError> for JSoundErrors {
+
+    fn from(value: SymphoniaError) -> Self {
+        match value {
+            SymphoniaError::IoError(e) => JSoundErrors::Io(e),
+            SymphoniaError::Unsupported(reason) => JSoundErrors::UnsupportedFormat(String::from(reason)),
+            e => JSoundErrors::Decode(e.to_string())
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JSoundFormat {
+    Wav,
+    Mp3,
+    Ogg,
+    Flac
+}
+
+impl JSoundFormat {
+
+    /// Guess the file format from the first bytes of the file.
+    pub fn sniff(magic: &[u8]) -> Option<JSoundFormat> {
+        if magic.len() >= 12 && &magic[..4] == b"RIFF" && &magic[8..12] == b"WAVE" {
+            return Some(JSoundFormat::Wav);
+        }
+        if magic.starts_with(b"OggS") {
+            return Some(JSoundFormat::Ogg);
+        }
+        if magic.starts_with(b"fLaC") {
+            return Some(JSoundFormat::Flac);
+        }
+        // either an id3v2 tag or a mpeg audio layer 3 frame sync
+        if magic.starts_with(b"ID3") || (magic.len() >= 2 && magic[0] == 0xFF && magic[1] & 0xE6 == 0xE2) {
+            return Some(JSoundFormat::Mp3);
+        }
+        None
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            JSoundFormat::Wav => "wav",
+            JSoundFormat::Mp3 => "mp3",
+            JSoundFormat::Ogg => "ogg",
+            JSoundFormat::Flac => "flac"
+        }
+    }
+}
+
+
+/// Samples decoded from a sound file, interleaved and normalized in the [-1.0, 1.0] range.
+pub struct DecodedSound {
+    pub data: Vec<f32>,
+    pub sample_rate: u32,
+    pub channels: u16
+}
+
+
+pub fn decode_file(path: &str) -> Result<DecodedSound, JSoundErrors> {
+    let file = std::fs::File::open(std::path::Path::new(path))?;
+    decode(file)
+}
+
+
+fn decode<S: MediaSource + 'static>(mut source: S) -> Result<DecodedSound, JSoundErrors> {
+    let mut magic = [0u8; 12];
+    let mut read = 0;
+    while read < magic.len() {
+        match source.read(&mut magic[read..])? {
+            0 => break,
+            n => read += n
+        }
+    }
+    source.seek(SeekFrom::Start(0))?;
+
+    match JSoundFormat::sniff(&magic[..read]) {
+        Some(JSoundFormat::Wav) => match decode_wav(&mut source) {
+            // extensible and other wav flavours the wav crate can't read
+            Err(JSoundErrors::UnsupportedFormat(_)) => {
+                source.seek(SeekFrom::Start(0))?;
+                decode_compressed(source, JSoundFormat::Wav)
+            },
+            result => result
+        },
+        Some(format) => decode_compressed(source, format),
+        None => Err(JSoundErrors::UnsupportedFormat(String::from("unknown file type")))
+    }
+}
+
+
+fn decode_wav<R: Read + Seek>(reader: &mut R) -> Result<DecodedSound, JSoundErrors> {
+    let (header, data) = wav::read(reader)?;
+    if header.channel_count == 0 || header.sampling_rate == 0 {
+        return Err(JSoundErrors::Decode(String::from("no channel or no sample rate in the wav header")));
+    }
+    let data = match data {
+        wav::BitDepth::Eight(v) => v
+            .into_iter()
+            .map(|s| (s as f32 - 128.0) / 128.0)
+            .collect(),
+        wav::BitDepth::Sixteen(v) => v
+            .into_iter()
+            .map(|s| s as f32 / 32768.0)
+            .collect(),
+        // the wav crate store 24 bits sample in the upper bytes of the i32
+        wav::BitDepth::TwentyFour(v) => v
+            .into_iter()
+            .map(|s| (s as f64 / 2147483648.0) as f32)
+            .collect(),
+        wav::BitDepth::ThirtyTwoFloat(v) => v,
+        wav::BitDepth::Empty => return Err(JSoundErrors::Empty)
+    };
+    if data.is_empty() {
+        return Err(JSoundErrors::Empty);
+    }
+    Ok(DecodedSound {
+        data,
+        sample_rate: header.sampling_rate,
+        channels: header.channel_count
+    })
+}
+
+
+fn decode_compressed<S: MediaSource + 'static>(source: S, format: JSoundFormat) -> Result<DecodedSound, JSoundErrors> {
+    let stream = MediaSourceStream::new(Box::new(source), Default::default());
+    let mut hint = Hint::new();
+    hint.with_extension(format.extension());
+
+    let probed = symphonia::default::get_probe().format(
+        &hint,
+        stream,
+        &FormatOptions::default(),
+        &MetadataOptions::default()
+    )?;
+    let mut reader = probed.format;
+    let track = reader
+        .tracks()
+        .iter()
+        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+        .ok_or(JSoundErrors::Empty)?;
+    let track_id = track.id;
+    let mut sample_rate = track.codec_params.sample_rate.unwrap_or(0);
+    let mut channels = track.codec_params.channels.map(|c| c.count()).unwrap_or(0);
+    let mut decoder = symphonia::default::get_codecs().make(&track.codec_params, &DecoderOptions::default())?;
+
+    let mut data = Vec::new();
+    let mut sample_buffer: Option<SampleBuffer<f32>> = None;
+    loop {
+        let packet = match reader.next_packet() {
+            Ok(packet) => packet,
+            // symphonia report the end of the stream as an eof io error
+            Err(SymphoniaError::IoError(e)) if e.kind() == std::io::ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into())
+        };
+        if packet.track_id() != track_id {
+            continue;
+        }
+        let audio = match decoder.decode(&packet) {
+            Ok(audio) => audio,
+            // a corrupted packet only skip a few milliseconds of audio.
+            Err(SymphoniaError::DecodeError(_)) => continue,
+            Err(e) => return Err(e.into())
+        };
+        let spec = *audio.spec();
+        sample_rate = spec.rate;
+        channels = spec.channels.count();
+
+        let needed = audio.capacity() * channels;
+        let too_small = match sample_buffer.as_ref() {
+            Some(buffer) => buffer.capacity() < needed,
+            None => true
+        };
+        if too_small {
+            sample_buffer = Some(SampleBuffer::new(audio.capacity() as u64, spec));
+        }
+        if let Some(buffer) = sample_buffer.as_mut() {
+            buffer.copy_interleaved_ref(audio);
+            data.extend_from_slice(buffer.samples());
+        }
+    }
+
+    if data.is_empty() {
+        return Err(JSoundErrors::Empty);
+    }
+    if channels == 0 || sample_rate == 0 {
+        return Err(JSoundErrors::Decode(String::from("no channel or no sample rate in the stream")));
+    }
+    Ok(DecodedSound {
+        data,
+        sample_rate,
+        channels: channels as u16
+    })
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{decode, decode_wav, JSoundErrors, JSoundFormat};
+
+    fn encode(header: wav::Header, data: wav::BitDepth) -> Vec<u8> {
+        let mut out = std::io::Cursor::new(Vec::new());
+        wav::write(header, &data, &mut out).unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn decode_integer_pcm_test() {
+        let header = wav::Header::new(wav::header::WAV_FORMAT_PCM, 1, 44100, 16);
+        let bytes = encode(header, wav::BitDepth::Sixteen(vec![0, 16384, -32768]));
+        let sound = decode_wav(&mut std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(sound.data, vec![0.0, 0.5, -1.0]);
+        assert_eq!(sound.sample_rate, 44100);
+
+        let header = wav::Header::new(wav::header::WAV_FORMAT_PCM, 1, 44100, 8);
+        let bytes = encode(header, wav::BitDepth::Eight(vec![128, 192, 0]));
+        let sound = decode_wav(&mut std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(sound.data, vec![0.0, 0.5, -1.0]);
+
+        let header = wav::Header::new(wav::header::WAV_FORMAT_PCM, 1, 44100, 24);
+        let bytes = encode(header, wav::BitDepth::TwentyFour(vec![0, 1 << 30, i32::MIN]));
+        let sound = decode_wav(&mut std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(sound.data, vec![0.0, 0.5, -1.0]);
+    }
+
+    #[test]
+    fn decode_unsupported_test() {
+        let header = wav::Header::new(wav::header::WAV_FORMAT_PCM, 1, 44100, 12);
+        let bytes = encode(header, wav::BitDepth::Sixteen(vec![0, 1]));
+        assert!(matches!(decode_wav(&mut std::io::Cursor::new(bytes)), Err(JSoundErrors::UnsupportedFormat(_))));
+    }
+
+    #[test]
+    fn decode_extensible_test() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"RIFF");
+        bytes.extend_from_slice(&66u32.to_le_bytes());
+        bytes.extend_from_slice(b"WAVEfmt ");
+        bytes.extend_from_slice(&40u32.to_le_bytes());
+        // WAVE_FORMAT_EXTENSIBLE, mono 8000Hz 16 bits
+        bytes.extend_from_slice(&0xFFFEu16.to_le_bytes());
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&8000u32.to_le_bytes());
+        bytes.extend_from_slice(&16000u32.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        // valid bits, front center channel mask and the pcm sub format guid
+        bytes.extend_from_slice(&22u16.to_le_bytes());
+        bytes.extend_from_slice(&16u16.to_le_bytes());
+        bytes.extend_from_slice(&4u32.to_le_bytes());
+        bytes.extend_from_slice(&[0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x00, 0x80, 0x00, 0x00, 0xAA, 0x00, 0x38, 0x9B, 0x71]);
+        bytes.extend_from_slice(b"data");
+        bytes.extend_from_slice(&6u32.to_le_bytes());
+        for sample in [0i16, 16384, -32768] {
+            bytes.extend_from_slice(&sample.to_le_bytes());
+        }
+
+        let sound = decode(std::io::Cursor::new(bytes)).unwrap();
+        assert_eq!(sound.data, vec![0.0, 0.5, -1.0]);
+        assert_eq!(sound.sample_rate, 8000);
+        assert_eq!(sound.channels, 1);
+    }
+
+    #[test]
+    fn decode_invalid_header_test() {
+        let header = wav::Header::new(wav::header::WAV_FORMAT_PCM, 0, 44100, 16);
+        let bytes = encode(header, wav::BitDepth::Sixteen(vec![0, 1]));
+        assert!(matches!(decode_wav(&mut std::io::Cursor::new(bytes)), Err(JSoundErrors::Decode(_))));
+
+        let header = wav::Header::new(wav::header::WAV_FORMAT_PCM, 1, 0, 16);
+        let bytes = encode(header, wav::BitDepth::Sixteen(vec![0, 1]));
+        assert!(matches!(decode_wav(&mut std::io::Cursor::new(bytes)), Err(JSoundErrors::Decode(_))));
+    }
+
+    #[test]
+    fn sniff_format_test() {
+        assert_eq!(JSoundFormat::sniff(b"RIFF\x24\x00\x00\x00WAVEfmt "), Some(JSoundFormat::Wav));
+        assert_eq!(JSoundFormat::sniff(b"OggS\x00\x02"), Some(JSoundFormat::Ogg));
+        assert_eq!(JSoundFormat::sniff(b"fLaC\x00\x00"), Some(JSoundFormat::Flac));
+        assert_eq!(JSoundFormat::sniff(b"ID3\x04\x00"), Some(JSoundFormat::Mp3));
+        assert_eq!(JSoundFormat::sniff(&[0xFF, 0xFB, 0x90, 0x64]), Some(JSoundFormat::Mp3));
+        assert_eq!(JSoundFormat::sniff(b"RIFF\x24\x00\x00\x00AVI "), None);
+    }
+}
@@ -0,0 +1,138 @@
+use serde::{Serialize, Deserialize};
+
+use crate::effects::smoothing_coefficient;
+use crate::gain::{db_to_gain, gain_to_db};
+
+
+// time for the microphone level to fall back once the voice stops
+const DETECTOR_RELEASE_MS: f32 = 100.0;
+
+
+/// What gets quieter when ducking.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum JDuckingMode {
+    /// the microphone, while any sound plays.
+    #[default]
+    Microphone,
+    /// the sounds, while the microphone level is above the threshold.
+    Sounds
+}
+
+
+/// Automatic level drop between the microphone and the sounds.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JDucking {
+    pub mode: JDuckingMode,
+    /// level drop in dB.
+    pub amount_db: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    /// microphone level that ducks the sounds, only used by `JDuckingMode::Sounds`.
+    pub threshold_db: f32
+}
+
+impl Default for JDucking {
+
+    fn default() -> Self {
+        JDucking {
+            mode: JDuckingMode::default(),
+            amount_db: 12.0,
+            attack_ms: 20.0,
+            release_ms: 300.0,
+            threshold_db: -30.0
+        }
+    }
+}
+
+
+/// Gain of the ducked signal, following a key that is on while the other signal is present.
+pub struct Ducker {
+    settings: JDucking,
+    sample_rate: u32,
+    attack: f32,
+    release: f32,
+    // level drop in dB
+    reduction: f32,
+    detector_release: f32,
+    // peak level of the microphone
+    level: f32
+}
+
+impl Ducker {
+
+    pub fn new(settings: JDucking, sample_rate: u32) -> Self {
+        let mut ducker = Ducker {
+            settings,
+            sample_rate,
+            attack: 0.0,
+            release: 0.0,
+            reduction: 0.0,
+            detector_release: smoothing_coefficient(DETECTOR_RELEASE_MS, sample_rate),
+            level: 0.0
+        };
+        ducker.set_settings(settings);
+        ducker
+    }
+
+    /// Change the settings, a ducking in progress keeps its current level.
+    pub fn set_settings(&mut self, settings: JDucking) {
+        self.settings = settings;
+        self.attack = smoothing_coefficient(settings.attack_ms, self.sample_rate);
+        self.release = smoothing_coefficient(settings.release_ms, self.sample_rate);
+    }
+
+    pub fn mode(&self) -> JDuckingMode {
+        self.settings.mode
+    }
+
+    /// Follow the level of a microphone frame, true while it is above the threshold.
+    pub fn detect(&mut self, frame: &[f32]) -> bool {
+        let peak = frame.iter().fold(0.0f32, |p, s| p.max(s.abs()));
+        self.level = peak.max(self.level * self.detector_release);
+        gain_to_db(self.level) > self.settings.threshold_db
+    }
+
+    /// Gain for the next frame, `key` is on while the ducked signal must be quieter.
+    pub fn next_gain(&mut self, key: bool) -> f32 {
+        let target = if key { self.settings.amount_db.max(0.0) } else { 0.0 };
+        let coefficient = if target > self.reduction { self.attack } else { self.release };
+        self.reduction = target + (self.reduction - target) * coefficient;
+        db_to_gain(-self.reduction)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Ducker, JDucking, JDuckingMode};
+    use crate::gain::gain_to_db;
+
+    #[test]
+    fn ducking_test() {
+        let settings = JDucking { amount_db: 12.0, attack_ms: 10.0, release_ms: 100.0, ..JDucking::default() };
+        let mut ducker = Ducker::new(settings, 1000);
+        assert_eq!(ducker.next_gain(false), 1.0);
+
+        // fully ducked a few attack times later
+        let gain = (0..50).map(|_| ducker.next_gain(true)).last().unwrap();
+        assert!((gain_to_db(gain) + 12.0).abs() < 0.1, "{gain}");
+
+        // the release is slower than the attack
+        let gain = (0..50).map(|_| ducker.next_gain(false)).last().unwrap();
+        assert!(gain_to_db(gain) < -6.0, "{gain}");
+        let gain = (0..1000).map(|_| ducker.next_gain(false)).last().unwrap();
+        assert!(gain_to_db(gain) > -0.1, "{gain}");
+    }
+
+    #[test]
+    fn threshold_test() {
+        let settings = JDucking { mode: JDuckingMode::Sounds, threshold_db: -30.0, ..JDucking::default() };
+        let mut ducker = Ducker::new(settings, 1000);
+        assert!(!ducker.detect(&[0.01, -0.01]));
+        assert!(ducker.detect(&[0.1, -0.5]));
+        // the level falls back slowly between words
+        assert!(ducker.detect(&[0.0, 0.0]));
+        assert!((0..1000).map(|_| ducker.detect(&[0.0, 0.0])).any(|above| !above));
+    }
+}
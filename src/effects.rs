@@ -0,0 +1,664 @@
+use std::collections::VecDeque;
+
+use serde::{Serialize, Deserialize};
+
+use crate::gain::{db_to_gain, gain_to_db};
+use crate::loudness::Biquad;
+use crate::pitch::JPlayback;
+use crate::source::JFrameSource;
+
+
+const LIMITER_LOOKAHEAD_SECONDS: f32 = 0.0015;
+// freeverb tunings at 44.1kHz
+const COMB_TUNINGS: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNINGS: [usize; 4] = [556, 441, 341, 225];
+const STEREO_SPREAD: usize = 23;
+const REVERB_INPUT_GAIN: f32 = 0.015;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum JFilterType {
+    Peak,
+    LowShelf,
+    HighShelf,
+    LowPass,
+    HighPass
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct JEqBand {
+    pub filter: JFilterType,
+    pub frequency: f32,
+    /// ignored by the low and high pass filters.
+    pub gain_db: f32,
+    pub q: f32
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JCompressor {
+    pub threshold_db: f32,
+    pub ratio: f32,
+    pub attack_ms: f32,
+    pub release_ms: f32,
+    pub makeup_db: f32
+}
+
+impl Default for JCompressor {
+
+    fn default() -> Self {
+        JCompressor {
+            threshold_db: -18.0,
+            ratio: 4.0,
+            attack_ms: 5.0,
+            release_ms: 100.0,
+            makeup_db: 0.0
+        }
+    }
+}
+
+
+/// Brickwall limiter, the output never goes over the ceiling.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JLimiter {
+    pub ceiling_db: f32,
+    pub release_ms: f32
+}
+
+impl Default for JLimiter {
+
+    fn default() -> Self {
+        JLimiter {
+            ceiling_db: -1.0,
+            release_ms: 50.0
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JReverb {
+    /// 0.0 to 1.0, longer tails for bigger rooms.
+    pub room_size: f32,
+    /// 0.0 to 1.0, how fast the high frequencies die.
+    pub damping: f32,
+    /// level of the reverberated sound added to the dry one.
+    pub wet: f32
+}
+
+impl Default for JReverb {
+
+    fn default() -> Self {
+        JReverb {
+            room_size: 0.5,
+            damping: 0.5,
+            wet: 0.25
+        }
+    }
+}
+
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum JEffect {
+    Eq(Vec<JEqBand>),
+    Compressor(JCompressor),
+    Limiter(JLimiter),
+    Reverb(JReverb)
+}
+
+
+trait Effect: Send {
+
+    fn process(&mut self, frame: &mut [f32]);
+
+    /// Take new settings, return false if they are for another kind of effect.
+    fn update(&mut self, settings: &JEffect) -> bool;
+
+    /// Frames it keeps sounding once its input is silent.
+    fn tail(&self) -> usize {
+        0
+    }
+}
+
+
+/// Audio EQ cookbook coefficients.
+fn band_coefficients(band: &JEqBand, sample_rate: u32) -> ([f64; 3], [f64; 2]) {
+    let frequency = (band.frequency as f64).clamp(10.0, sample_rate as f64 * 0.49);
+    let w0 = 2.0 * std::f64::consts::PI * frequency / sample_rate as f64;
+    let (sin, cos) = w0.sin_cos();
+    let alpha = sin / (2.0 * (band.q as f64).max(0.01));
+    let a = 10f64.powf(band.gain_db as f64 / 40.0);
+    let shelf = 2.0 * a.sqrt() * alpha;
+    let (b, a) = match band.filter {
+        JFilterType::Peak => (
+            [1.0 + alpha * a, -2.0 * cos, 1.0 - alpha * a],
+            [1.0 + alpha / a, -2.0 * cos, 1.0 - alpha / a]
+        ),
+        JFilterType::LowShelf => (
+            [
+                a * ((a + 1.0) - (a - 1.0) * cos + shelf),
+                2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+                a * ((a + 1.0) - (a - 1.0) * cos - shelf)
+            ],
+            [
+                (a + 1.0) + (a - 1.0) * cos + shelf,
+                -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+                (a + 1.0) + (a - 1.0) * cos - shelf
+            ]
+        ),
+        JFilterType::HighShelf => (
+            [
+                a * ((a + 1.0) + (a - 1.0) * cos + shelf),
+                -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+                a * ((a + 1.0) + (a - 1.0) * cos - shelf)
+            ],
+            [
+                (a + 1.0) - (a - 1.0) * cos + shelf,
+                2.0 * ((a - 1.0) - (a + 1.0) * cos),
+                (a + 1.0) - (a - 1.0) * cos - shelf
+            ]
+        ),
+        JFilterType::LowPass => (
+            [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha]
+        ),
+        JFilterType::HighPass => (
+            [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+            [1.0 + alpha, -2.0 * cos, 1.0 - alpha]
+        )
+    };
+    ([b[0] / a[0], b[1] / a[0], b[2] / a[0]], [a[1] / a[0], a[2] / a[0]])
+}
+
+
+struct Eq {
+    sample_rate: u32,
+    // one filter per band and channel
+    filters: Vec<Vec<Biquad>>
+}
+
+impl Eq {
+
+    fn new(bands: &[JEqBand], channels: usize, sample_rate: u32) -> Self {
+        let filters = bands
+            .iter()
+            .map(|band| {
+                let (b, a) = band_coefficients(band, sample_rate);
+                (0..channels).map(|_| Biquad::new(b, a)).collect()
+            })
+            .collect();
+        Eq { sample_rate, filters }
+    }
+}
+
+impl Effect for Eq {
+
+    fn process(&mut self, frame: &mut [f32]) {
+        for band in self.filters.iter_mut() {
+            for (sample, filter) in frame.iter_mut().zip(band.iter_mut()) {
+                *sample = filter.process(*sample as f64) as f32;
+            }
+        }
+    }
+
+    fn update(&mut self, settings: &JEffect) -> bool {
+        match settings {
+            JEffect::Eq(bands) if bands.len() == self.filters.len() => {
+                for (band, filters) in bands.iter().zip(self.filters.iter_mut()) {
+                    let (b, a) = band_coefficients(band, self.sample_rate);
+                    filters.iter_mut().for_each(|f| f.set_coefficients(b, a));
+                }
+                true
+            },
+            _ => false
+        }
+    }
+}
+
+
+/// Coefficient of a one pole smoother reaching 63% of its target in `ms`.
+pub fn smoothing_coefficient(ms: f32, sample_rate: u32) -> f32 {
+    let frames = ms.max(0.01) * sample_rate as f32 / 1000.0;
+    (-1.0 / frames).exp()
+}
+
+
+struct Compressor {
+    settings: JCompressor,
+    sample_rate: u32,
+    attack: f32,
+    release: f32,
+    // gain reduction in dB
+    reduction: f32
+}
+
+impl Compressor {
+
+    fn new(settings: JCompressor, sample_rate: u32) -> Self {
+        let mut compressor = Compressor {
+            settings,
+            sample_rate,
+            attack: 0.0,
+            release: 0.0,
+            reduction: 0.0
+        };
+        compressor.update(&JEffect::Compressor(settings));
+        compressor
+    }
+}
+
+impl Effect for Compressor {
+
+    fn process(&mut self, frame: &mut [f32]) {
+        // channels are linked so the stereo image doesn't move
+        let level = gain_to_db(frame.iter().fold(0.0f32, |p, s| p.max(s.abs())));
+        let over = level - self.settings.threshold_db;
+        let target = if over > 0.0 { over * (1.0 - 1.0 / self.settings.ratio.max(1.0)) } else { 0.0 };
+        let coefficient = if target > self.reduction { self.attack } else { self.release };
+        self.reduction = target + (self.reduction - target) * coefficient;
+        let gain = db_to_gain(self.settings.makeup_db - self.reduction);
+        frame.iter_mut().for_each(|s| *s *= gain);
+    }
+
+    fn update(&mut self, settings: &JEffect) -> bool {
+        match settings {
+            JEffect::Compressor(settings) => {
+                self.settings = *settings;
+                self.attack = smoothing_coefficient(settings.attack_ms, self.sample_rate);
+                self.release = smoothing_coefficient(settings.release_ms, self.sample_rate);
+                true
+            },
+            _ => false
+        }
+    }
+}
+
+
+/// Lookahead limiter, the gain needed by a peak is reached before the peak leaves the delay.
+struct Limiter {
+    ceiling: f32,
+    release: f32,
+    sample_rate: u32,
+    lookahead: usize,
+    delay: VecDeque<f32>,
+    // gains needed by the frames in the lookahead window, increasing from the front
+    held: VecDeque<(usize, f32)>,
+    frame_index: usize,
+    envelope: f32,
+    // moving average of the envelope over the lookahead
+    average: VecDeque<f32>,
+    average_sum: f64
+}
+
+impl Limiter {
+
+    fn new(settings: JLimiter, channels: usize, sample_rate: u32) -> Self {
+        let lookahead = ((sample_rate as f32 * LIMITER_LOOKAHEAD_SECONDS) as usize).max(1);
+        // room for the frame pushed before the oldest one is popped, nothing grows on the audio thread
+        let mut delay = VecDeque::with_capacity((lookahead + 1) * channels);
+        delay.resize(lookahead * channels, 0.0);
+        let mut limiter = Limiter {
+            ceiling: 1.0,
+            release: 0.0,
+            sample_rate,
+            lookahead,
+            delay,
+            held: VecDeque::with_capacity(lookahead + 2),
+            frame_index: 0,
+            envelope: 1.0,
+            average: VecDeque::from(vec![1.0; lookahead]),
+            average_sum: lookahead as f64
+        };
+        limiter.update(&JEffect::Limiter(settings));
+        limiter
+    }
+}
+
+impl Effect for Limiter {
+
+    fn process(&mut self, frame: &mut [f32]) {
+        let peak = frame.iter().fold(0.0f32, |p, s| p.max(s.abs()));
+        let needed = if peak > self.ceiling { self.ceiling / peak } else { 1.0 };
+
+        // lowest gain needed over the lookahead window
+        while self.held.back().is_some_and(|(_, g)| *g >= needed) {
+            self.held.pop_back();
+        }
+        self.held.push_back((self.frame_index, needed));
+        while self.held.front().is_some_and(|(i, _)| i + self.lookahead < self.frame_index) {
+            self.held.pop_front();
+        }
+        self.frame_index += 1;
+        let held = self.held.front().map(|(_, g)| *g).unwrap_or(1.0);
+
+        self.envelope = if held < self.envelope { held } else { held + (self.envelope - held) * self.release };
+        self.average_sum += self.envelope as f64 - self.average.pop_front().unwrap_or(1.0) as f64;
+        self.average.push_back(self.envelope);
+        let gain = (self.average_sum / self.lookahead as f64) as f32;
+
+        self.delay.extend(frame.iter());
+        for sample in frame.iter_mut() {
+            let delayed = self.delay.pop_front().unwrap_or(0.0);
+            // the clamp only catches rounding errors
+            *sample = (delayed * gain).clamp(-self.ceiling, self.ceiling);
+        }
+    }
+
+    fn update(&mut self, settings: &JEffect) -> bool {
+        match settings {
+            JEffect::Limiter(settings) => {
+                self.ceiling = db_to_gain(settings.ceiling_db.min(0.0));
+                self.release = smoothing_coefficient(settings.release_ms, self.sample_rate);
+                true
+            },
+            _ => false
+        }
+    }
+
+    fn tail(&self) -> usize {
+        self.lookahead
+    }
+}
+
+
+struct Comb {
+    buffer: Vec<f32>,
+    index: usize,
+    filtered: f32
+}
+
+impl Comb {
+
+    fn process(&mut self, input: f32, feedback: f32, damping: f32) -> f32 {
+        let output = self.buffer[self.index];
+        self.filtered = output * (1.0 - damping) + self.filtered * damping;
+        self.buffer[self.index] = input + self.filtered * feedback;
+        self.index = (self.index + 1) % self.buffer.len();
+        output
+    }
+}
+
+
+struct Allpass {
+    buffer: Vec<f32>,
+    index: usize
+}
+
+impl Allpass {
+
+    fn process(&mut self, input: f32) -> f32 {
+        let delayed = self.buffer[self.index];
+        self.buffer[self.index] = input + delayed * 0.5;
+        self.index = (self.index + 1) % self.buffer.len();
+        delayed - input
+    }
+}
+
+
+/// Freeverb, each channel has its own slightly detuned tank.
+struct Reverb {
+    settings: JReverb,
+    tanks: Vec<(Vec<Comb>, Vec<Allpass>)>
+}
+
+impl Reverb {
+
+    fn new(settings: JReverb, channels: usize, sample_rate: u32) -> Self {
+        let scale = |len: usize, channel: usize| {
+            let spread = if channel % 2 == 1 { STEREO_SPREAD } else { 0 };
+            (((len + spread) as f64 * sample_rate as f64 / 44100.0) as usize).max(1)
+        };
+        let tanks = (0..channels)
+            .map(|channel| {
+                let combs = COMB_TUNINGS
+                    .iter()
+                    .map(|len| Comb { buffer: vec![0.0; scale(*len, channel)], index: 0, filtered: 0.0 })
+                    .collect();
+                let allpasses = ALLPASS_TUNINGS
+                    .iter()
+                    .map(|len| Allpass { buffer: vec![0.0; scale(*len, channel)], index: 0 })
+                    .collect();
+                (combs, allpasses)
+            })
+            .collect();
+        Reverb { settings, tanks }
+    }
+
+    fn feedback(&self) -> f32 {
+        0.7 + 0.28 * self.settings.room_size.clamp(0.0, 1.0)
+    }
+}
+
+impl Effect for Reverb {
+
+    fn process(&mut self, frame: &mut [f32]) {
+        let input = frame.iter().sum::<f32>() * REVERB_INPUT_GAIN;
+        let feedback = self.feedback();
+        let damping = 0.4 * self.settings.damping.clamp(0.0, 1.0);
+        for (sample, (combs, allpasses)) in frame.iter_mut().zip(self.tanks.iter_mut()) {
+            let mut wet: f32 = combs.iter_mut().map(|c| c.process(input, feedback, damping)).sum();
+            for allpass in allpasses.iter_mut() {
+                wet = allpass.process(wet);
+            }
+            *sample += wet * self.settings.wet;
+        }
+    }
+
+    fn update(&mut self, settings: &JEffect) -> bool {
+        match settings {
+            JEffect::Reverb(settings) => {
+                self.settings = *settings;
+                true
+            },
+            _ => false
+        }
+    }
+
+    fn tail(&self) -> usize {
+        // time for the longest comb to decay by 60dB
+        let longest = self.tanks
+            .iter()
+            .flat_map(|(combs, _)| combs.iter().map(|c| c.buffer.len()))
+            .max()
+            .unwrap_or(0);
+        (longest as f32 * 0.001f32.ln() / self.feedback().ln()) as usize
+    }
+}
+
+
+fn build_effect(settings: &JEffect, channels: usize, sample_rate: u32) -> Box<dyn Effect> {
+    match settings {
+        JEffect::Eq(bands) => Box::new(Eq::new(bands, channels, sample_rate)),
+        JEffect::Compressor(settings) => Box::new(Compressor::new(*settings, sample_rate)),
+        JEffect::Limiter(settings) => Box::new(Limiter::new(*settings, channels, sample_rate)),
+        JEffect::Reverb(settings) => Box::new(Reverb::new(*settings, channels, sample_rate))
+    }
+}
+
+
+/// Effects applied one after the other on interleaved frames.
+/// Building a chain allocates, it is done outside the audio thread.
+pub struct EffectChain {
+    effects: Vec<Box<dyn Effect>>,
+    settings: Vec<JEffect>
+}
+
+impl EffectChain {
+
+    pub fn new(settings: &[JEffect], channels: usize, sample_rate: u32) -> Self {
+        EffectChain {
+            effects: settings.iter().map(|s| build_effect(s, channels, sample_rate)).collect(),
+            settings: settings.to_vec()
+        }
+    }
+
+    /// Take the effects of `chain` without allocating, effects of the same kind at the same place keep their state.
+    /// Return the chain holding everything left over, it must be dropped outside the audio thread.
+    pub fn replace(&mut self, mut chain: EffectChain) -> EffectChain {
+        std::mem::swap(self, &mut chain);
+        for ((old, new), settings) in chain.effects.iter_mut().zip(self.effects.iter_mut()).zip(self.settings.iter()) {
+            if old.update(settings) {
+                std::mem::swap(old, new);
+            }
+        }
+        chain
+    }
+
+    #[inline]
+    pub fn process(&mut self, frame: &mut [f32]) {
+        for effect in self.effects.iter_mut() {
+            effect.process(frame);
+        }
+    }
+
+    pub fn tail(&self) -> usize {
+        self.effects.iter().map(|e| e.tail()).sum()
+    }
+}
+
+
+/// Run a source through an effect chain, the effects tails play after the source ends.
+pub struct EffectSource<S: JFrameSource> {
+    source: S,
+    chain: EffectChain,
+    // frames left once the source ended
+    tail: Option<usize>
+}
+
+impl<S: JFrameSource> EffectSource<S> {
+
+    pub fn new(source: S, settings: &[JEffect], sample_rate: u32) -> Self {
+        let chain = EffectChain::new(settings, source.channels(), sample_rate);
+        EffectSource { source, chain, tail: None }
+    }
+}
+
+impl<S: JFrameSource> JFrameSource for EffectSource<S> {
+
+    fn channels(&self) -> usize {
+        self.source.channels()
+    }
+
+    fn next_frame(&mut self, frame: &mut [f32]) -> bool {
+        match self.tail {
+            None if self.source.next_frame(frame) => (),
+            None => {
+                self.tail = Some(self.chain.tail());
+                return self.next_frame(frame);
+            },
+            Some(0) => return false,
+            Some(ref mut left) => {
+                *left -= 1;
+                frame.fill(0.0);
+            }
+        }
+        self.chain.process(frame);
+        true
+    }
+
+    fn seek(&mut self, frame: usize) -> bool {
+        self.tail = None;
+        self.source.seek(frame)
+    }
+
+    fn position(&self) -> Option<usize> {
+        self.source.position()
+    }
+
+    fn set_playback(&mut self, playback: JPlayback) -> bool {
+        self.source.set_playback(playback)
+    }
+
+    fn playback_rate(&self) -> f64 {
+        self.source.playback_rate()
+    }
+
+    fn set_effects(&mut self, chain: EffectChain) -> EffectChain {
+        self.chain.replace(chain)
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{Effect, EffectChain, JCompressor, JEffect, JEqBand, JFilterType, JLimiter, JReverb, Limiter};
+    use crate::gain::gain_to_db;
+
+    fn sine(frequency: f32, amplitude: f32, frames: usize, sample_rate: u32) -> Vec<f32> {
+        (0..frames)
+            .map(|i| amplitude * (2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate as f32).sin())
+            .collect()
+    }
+
+    fn process(chain: &mut EffectChain, data: &[f32]) -> Vec<f32> {
+        data.iter()
+            .map(|s| {
+                let mut frame = [*s];
+                chain.process(&mut frame);
+                frame[0]
+            })
+            .collect()
+    }
+
+    fn peak(data: &[f32]) -> f32 {
+        data.iter().fold(0.0f32, |p, s| p.max(s.abs()))
+    }
+
+    #[test]
+    fn eq_compressor_test() {
+        let band = JEqBand { filter: JFilterType::Peak, frequency: 1000.0, gain_db: 6.0, q: 1.0 };
+        let mut chain = EffectChain::new(&[JEffect::Eq(vec![band])], 1, 48000);
+        let output = process(&mut chain, &sine(1000.0, 0.25, 48000, 48000));
+        assert!((gain_to_db(peak(&output[24000..]) / 0.25) - 6.0).abs() < 0.1);
+
+        // 12dB over the threshold at 4:1 leaves 3dB, less the release between the peaks
+        let compressor = JCompressor { threshold_db: -24.0, ratio: 4.0, ..Default::default() };
+        let mut chain = EffectChain::new(&[JEffect::Compressor(compressor)], 1, 48000);
+        let output = process(&mut chain, &sine(1000.0, 0.25, 48000, 48000));
+        assert!((gain_to_db(peak(&output[24000..])) + 21.0).abs() < 1.0);
+    }
+
+    #[test]
+    fn limiter_test() {
+        let limiter = JLimiter { ceiling_db: -6.0, release_ms: 10.0 };
+        let mut chain = EffectChain::new(&[JEffect::Limiter(limiter)], 1, 48000);
+        let mut data = sine(440.0, 0.2, 4800, 48000);
+        // a few stacked sounds suddenly going over
+        data.extend(sine(440.0, 3.0, 4800, 48000));
+        let output = process(&mut chain, &data);
+        assert!(peak(&output) <= 0.5012);
+        // quiet parts are left alone
+        assert!((peak(&output[1000..4000]) - 0.2).abs() < 1e-4);
+    }
+
+    #[test]
+    fn limiter_capacity_test() {
+        let mut limiter = Limiter::new(JLimiter::default(), 2, 48000);
+        let capacities = |l: &Limiter| (l.delay.capacity(), l.held.capacity(), l.average.capacity());
+        let before = capacities(&limiter);
+        // a decreasing level keeps every frame in the lookahead window
+        for i in 0..4800 {
+            let level = 4.0 - i as f32 / 1200.0;
+            let mut frame = [level, -level];
+            limiter.process(&mut frame);
+        }
+        assert_eq!(capacities(&limiter), before);
+    }
+
+    #[test]
+    fn reverb_tail_test() {
+        let mut chain = EffectChain::new(&[JEffect::Reverb(JReverb::default())], 2, 48000);
+        assert!(chain.tail() > 48000);
+        let mut output = Vec::new();
+        for i in 0..24000 {
+            let mut frame = if i == 0 { [1.0, 1.0] } else { [0.0, 0.0] };
+            chain.process(&mut frame);
+            output.push(frame[0]);
+        }
+        assert!(peak(&output[12000..]) > 1e-4);
+    }
+}
@@ -0,0 +1,414 @@
+use std::sync::mpsc::Sender;
+
+use cpal::Device;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use rtrb::{Consumer, Producer, RingBuffer};
+
+use crate::channel_map::ChannelMapper;
+use crate::ducking::{Ducker, JDucking, JDuckingMode};
+use crate::effects::EffectChain;
+use crate::input::build_input_stream;
+use crate::mixer::{JVoiceEvent, JVoiceStealPolicy, Mixer, SequenceRunId, VoiceFades, VoiceId};
+use crate::app::JAppEvent;
+use crate::bridge::{bridge_buffer, send_block, BridgeReader};
+use crate::output::{build_output_stream, MAX_BLOCK_FRAMES};
+use crate::pitch::JPlayback;
+use crate::smoothed::SmoothedValue;
+use crate::source::JFrameSource;
+
+
+// commands that can wait for the next audio block, far more than a burst of hotkeys
+const COMMAND_QUEUE_SIZE: usize = 1024;
+// the app empties both on every event loop
+const EVENT_QUEUE_SIZE: usize = 4096;
+const GARBAGE_QUEUE_SIZE: usize = 1024;
+// time taken by an output volume change to reach its target
+const OUTPUT_GAIN_RAMP_SECONDS: f32 = 0.02;
+
+
+/// Change to the mixer, applied by the audio thread at the start of the next block.
+pub enum EngineCommand {
+    AddVoice {
+        id: VoiceId,
+        source: Box<dyn JFrameSource>,
+        fades: VoiceFades,
+        choke_group: Option<u32>
+    },
+    ScheduleVoice {
+        delay: usize,
+        id: VoiceId,
+        source: Box<dyn JFrameSource>,
+        fades: VoiceFades,
+        choke_group: Option<u32>
+    },
+    /// delay and run of the sequence stopping every voice.
+    ScheduleStopAll(usize, SequenceRunId),
+    CancelSequenceRun(SequenceRunId),
+    StopVoice(VoiceId),
+    StopAll,
+    PauseVoice(VoiceId),
+    ResumeVoice(VoiceId),
+    SeekVoice {
+        id: VoiceId,
+        frame: usize,
+        played: usize
+    },
+    SetVoicePlayback(VoiceId, JPlayback),
+    /// the chain is built by the app thread, building one allocates.
+    SetVoiceEffects(VoiceId, EffectChain),
+    SetMasterGain(f32),
+    SetMasterEffects(EffectChain),
+    SetVoiceLimit(usize, JVoiceStealPolicy),
+    /// gain of the primary output, after the master effects.
+    SetOutputGain(f32),
+    /// gain of the monitor output, after the master effects.
+    SetMonitorGain(f32),
+    SetMonitor(Option<Producer<f32>>),
+    /// gain of the microphone mixed into the primary output.
+    SetMicrophoneGain(f32),
+    SetMicrophone(Option<ChannelMapper<BridgeReader>>),
+    SetDucking(Option<JDucking>)
+}
+
+
+/// Something the audio thread is done with, freeing memory can block so the app thread drops it.
+enum Garbage {
+    Effects(EffectChain),
+    Source(Box<dyn JFrameSource>),
+    Monitor(Producer<f32>),
+    Microphone(ChannelMapper<BridgeReader>)
+}
+
+
+/// Everything owned by the audio thread of the primary output.
+struct EngineState {
+    mixer: Mixer,
+    channels: usize,
+    sample_rate: u32,
+    output_gain: SmoothedValue,
+    monitor_gain: SmoothedValue,
+    monitor: Option<Producer<f32>>,
+    monitor_block: Vec<f32>,
+    microphone: Option<ChannelMapper<BridgeReader>>,
+    microphone_gain: SmoothedValue,
+    microphone_block: Vec<f32>,
+    ducker: Option<Ducker>,
+    events: Producer<(VoiceId, JVoiceEvent)>,
+    garbage: Producer<Garbage>
+}
+
+impl EngineState {
+
+    fn discard(&mut self, garbage: Garbage) {
+        // dropped here only when the app stopped collecting
+        let _ = self.garbage.push(garbage);
+    }
+
+    /// Hand the mixer events and the sources it is done with to the app thread.
+    fn flush_mixer(&mut self) {
+        for event in self.mixer.drain_events() {
+            let _ = self.events.push(event);
+        }
+        for source in self.mixer.drain_dead() {
+            let _ = self.garbage.push(Garbage::Source(source));
+        }
+    }
+
+    fn run_commands(&mut self, commands: &mut Consumer<EngineCommand>) {
+        while let Ok(command) = commands.pop() {
+            let mixer = &mut self.mixer;
+            match command {
+                EngineCommand::AddVoice { id, source, fades, choke_group } => {
+                    mixer.add_voice(id, source, fades, choke_group);
+                },
+                EngineCommand::ScheduleVoice { delay, id, source, fades, choke_group } => {
+                    mixer.schedule_voice(delay, id, source, fades, choke_group);
+                },
+                EngineCommand::ScheduleStopAll(delay, owner) => mixer.schedule_stop_all(delay, owner),
+                EngineCommand::CancelSequenceRun(run) => mixer.cancel_sequence_run(run),
+                EngineCommand::StopVoice(id) => mixer.stop_voice(id),
+                EngineCommand::StopAll => mixer.stop_all(),
+                EngineCommand::PauseVoice(id) => mixer.pause_voice(id),
+                EngineCommand::ResumeVoice(id) => mixer.resume_voice(id),
+                EngineCommand::SeekVoice { id, frame, played } => mixer.seek_voice(id, frame, played),
+                EngineCommand::SetVoicePlayback(id, playback) => mixer.set_voice_playback(id, playback),
+                EngineCommand::SetVoiceEffects(id, chain) => {
+                    let replaced = mixer.set_voice_effects(id, chain);
+                    self.discard(Garbage::Effects(replaced));
+                },
+                EngineCommand::SetMasterGain(gain) => mixer.set_master_gain(gain),
+                EngineCommand::SetMasterEffects(chain) => {
+                    let replaced = mixer.set_master_effects(chain);
+                    self.discard(Garbage::Effects(replaced));
+                },
+                EngineCommand::SetVoiceLimit(max_voices, steal_policy) => {
+                    mixer.set_max_voices(max_voices);
+                    mixer.set_steal_policy(steal_policy);
+                },
+                EngineCommand::SetOutputGain(gain) => self.output_gain.set_target(gain.max(0.0)),
+                EngineCommand::SetMonitorGain(gain) => self.monitor_gain.set_target(gain.max(0.0)),
+                EngineCommand::SetMonitor(monitor) => {
+                    if let Some(replaced) = std::mem::replace(&mut self.monitor, monitor) {
+                        self.discard(Garbage::Monitor(replaced));
+                    }
+                },
+                EngineCommand::SetMicrophoneGain(gain) => self.microphone_gain.set_target(gain.max(0.0)),
+                EngineCommand::SetMicrophone(microphone) => {
+                    if let Some(replaced) = std::mem::replace(&mut self.microphone, microphone) {
+                        self.discard(Garbage::Microphone(replaced));
+                    }
+                },
+                EngineCommand::SetDucking(settings) => {
+                    match (self.ducker.as_mut(), settings) {
+                        (Some(ducker), Some(settings)) => ducker.set_settings(settings),
+                        (_, settings) => self.ducker = settings.map(|s| Ducker::new(s, self.sample_rate))
+                    }
+                }
+            }
+            // the events of each command must fit in the mixer
+            self.flush_mixer();
+        }
+    }
+
+    /// Render the mix, duck it, send it to the monitor, add the microphone then apply the primary output gain.
+    /// The monitor doesn't get the microphone, nobody wants to hear their own voice late.
+    fn render(&mut self, output: &mut [f32]) {
+        // the sounds of a sequence duck the microphone once they start, not when it is queued
+        let voices_active = self.mixer.is_sounding();
+        self.mixer.render(output);
+
+        // the microphone is read first, it can duck the sounds
+        let microphone_block = &mut self.microphone_block[..output.len()];
+        match self.microphone.as_mut() {
+            Some(microphone) => {
+                for frame in microphone_block.chunks_mut(self.channels) {
+                    microphone.next_frame(frame);
+                    let gain = self.microphone_gain.next_value();
+                    frame.iter_mut().for_each(|s| *s *= gain);
+                }
+            },
+            None => microphone_block.fill(0.0)
+        }
+        if let Some(ducker) = self.ducker.as_mut() {
+            for (frame, microphone_frame) in output.chunks_mut(self.channels).zip(microphone_block.chunks_mut(self.channels)) {
+                match ducker.mode() {
+                    JDuckingMode::Microphone => {
+                        let gain = ducker.next_gain(voices_active);
+                        microphone_frame.iter_mut().for_each(|s| *s *= gain);
+                    },
+                    JDuckingMode::Sounds => {
+                        let key = ducker.detect(microphone_frame);
+                        let gain = ducker.next_gain(key);
+                        frame.iter_mut().for_each(|s| *s *= gain);
+                    }
+                }
+            }
+        }
+
+        if let Some(monitor) = self.monitor.as_mut() {
+            let block = &mut self.monitor_block[..output.len()];
+            for (monitor_frame, frame) in block.chunks_mut(self.channels).zip(output.chunks(self.channels)) {
+                let gain = self.monitor_gain.next_value();
+                monitor_frame.iter_mut().zip(frame).for_each(|(m, s)| *m = s * gain);
+            }
+            send_block(monitor, block);
+        }
+        for (frame, microphone_frame) in output.chunks_mut(self.channels).zip(microphone_block.chunks(self.channels)) {
+            let gain = self.output_gain.next_value();
+            frame.iter_mut().zip(microphone_frame).for_each(|(s, m)| *s = (*s + m) * gain);
+        }
+    }
+}
+
+/// One of the streams of the audio engine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JAudioStream {
+    Output,
+    Monitor,
+    Microphone
+}
+
+
+/// Error callback of an engine stream, tagged so the app only rebuilds that stream of that engine.
+fn forward_stream_error(
+    sender: Sender<JAppEvent>,
+    stream: JAudioStream,
+    generation: usize
+) -> impl FnMut(cpal::StreamError) + Send + 'static {
+    move |err| {
+        let _ = sender.send(JAppEvent::AudioStreamError { stream, generation, reason: err.to_string() });
+    }
+}
+
+/// Output stream open for the whole life of the app, the mixer lives on its audio thread.
+pub struct AudioEngine {
+    // dropping the stream stop it.
+    _stream: cpal::Stream,
+    channels: usize,
+    sample_rate: u32,
+    commands: Producer<EngineCommand>,
+    events: Consumer<(VoiceId, JVoiceEvent)>,
+    garbage: Consumer<Garbage>,
+    errors: Sender<JAppEvent>,
+    generation: usize,
+    monitor: Option<cpal::Stream>,
+    microphone: Option<cpal::Stream>
+}
+
+impl AudioEngine {
+
+    /// Start the output stream of `device`, `build_mixer` receives its channels and sample rate.
+    /// Stream errors are sent to `errors` with the `generation` of the engine.
+    pub fn new<F>(device: &Device, errors: Sender<JAppEvent>, generation: usize, build_mixer: F) -> Result<Self, String>
+    where
+        F: FnOnce(usize, u32) -> Mixer
+    {
+        let config = device
+            .default_output_config()
+            .map_err(|e| format!("Couldn't get output device config {e}"))?;
+        let channels = config.channels() as usize;
+        let sample_rate = config.sample_rate().0;
+        let gain_ramp = (sample_rate as f32 * OUTPUT_GAIN_RAMP_SECONDS) as usize;
+        let (event_producer, events) = RingBuffer::new(EVENT_QUEUE_SIZE);
+        let (garbage_producer, garbage) = RingBuffer::new(GARBAGE_QUEUE_SIZE);
+        // the output stream never renders more than a block at once
+        let block_len = MAX_BLOCK_FRAMES * channels;
+        let mut state = EngineState {
+            mixer: build_mixer(channels, sample_rate),
+            channels,
+            sample_rate,
+            output_gain: SmoothedValue::new(1.0, gain_ramp),
+            monitor_gain: SmoothedValue::new(1.0, gain_ramp),
+            monitor: None,
+            monitor_block: vec![0.0; block_len],
+            microphone: None,
+            microphone_gain: SmoothedValue::new(1.0, gain_ramp),
+            microphone_block: vec![0.0; block_len],
+            ducker: None,
+            events: event_producer,
+            garbage: garbage_producer
+        };
+        let (producer, mut consumer) = RingBuffer::new(COMMAND_QUEUE_SIZE);
+
+        let stream = build_output_stream(
+            device,
+            &config,
+            move |output: &mut [f32]| {
+                state.run_commands(&mut consumer);
+                state.render(output);
+                state.flush_mixer();
+            },
+            forward_stream_error(errors.clone(), JAudioStream::Output, generation)
+        ).map_err(|e| format!("Error building stream {e} :("))?;
+        stream.play().map_err(|e| format!("Error starting stream {e} :("))?;
+
+        Ok(AudioEngine {
+            _stream: stream,
+            channels,
+            sample_rate,
+            commands: producer,
+            events,
+            garbage,
+            errors,
+            generation,
+            monitor: None,
+            microphone: None
+        })
+    }
+
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+
+    /// Queue a command for the audio thread without waiting, false when the queue is full.
+    pub fn send(&mut self, command: EngineCommand) -> bool {
+        self.commands.push(command).is_ok()
+    }
+
+    /// Next event of a voice published by the audio thread, oldest first.
+    pub fn next_event(&mut self) -> Option<(VoiceId, JVoiceEvent)> {
+        self.events.pop().ok()
+    }
+
+    /// Drop what the audio thread is done with.
+    pub fn collect_garbage(&mut self) {
+        while let Ok(garbage) = self.garbage.pop() {
+            match garbage {
+                Garbage::Effects(chain) => drop(chain),
+                Garbage::Source(source) => drop(source),
+                Garbage::Monitor(producer) => drop(producer),
+                Garbage::Microphone(microphone) => drop(microphone)
+            }
+        }
+    }
+
+    /// Play the same mix on a second device, or stop the monitor output with none.
+    pub fn set_monitor(&mut self, device: Option<&Device>) -> Result<(), String> {
+        self.send(EngineCommand::SetMonitor(None));
+        self.monitor = None;
+        let device = match device {
+            Some(device) => device,
+            None => return Ok(())
+        };
+        let config = device
+            .default_output_config()
+            .map_err(|e| format!("Couldn't get monitor device config {e}"))?;
+        let (producer, consumer) = bridge_buffer(self.channels, self.sample_rate);
+        let reader = BridgeReader::new(consumer, self.channels, self.sample_rate, config.sample_rate().0);
+        let monitor_channels = config.channels() as usize;
+        let mut source = ChannelMapper::new(reader, monitor_channels);
+        let stream = build_output_stream(
+            device,
+            &config,
+            move |output: &mut [f32]| {
+                for frame in output.chunks_mut(monitor_channels) {
+                    source.next_frame(frame);
+                }
+            },
+            forward_stream_error(self.errors.clone(), JAudioStream::Monitor, self.generation)
+        ).map_err(|e| format!("Error building monitor stream {e} :("))?;
+        stream.play().map_err(|e| format!("Error starting monitor stream {e} :("))?;
+        if !self.send(EngineCommand::SetMonitor(Some(producer))) {
+            return Err(String::from("Audio engine command queue is full"));
+        }
+        self.monitor = Some(stream);
+        Ok(())
+    }
+
+    /// Capture a device and mix it into the primary output, or stop the capture with none.
+    pub fn set_microphone(&mut self, device: Option<&Device>) -> Result<(), String> {
+        self.send(EngineCommand::SetMicrophone(None));
+        self.microphone = None;
+        let device = match device {
+            Some(device) => device,
+            None => return Ok(())
+        };
+        let config = device
+            .default_input_config()
+            .map_err(|e| format!("Couldn't get input device config {e}"))?;
+        let input_channels = config.channels() as usize;
+        let input_rate = config.sample_rate().0;
+        let (mut producer, consumer) = bridge_buffer(input_channels, input_rate);
+        let reader = BridgeReader::new(consumer, input_channels, input_rate, self.sample_rate);
+        let stream = build_input_stream(
+            device,
+            &config,
+            move |input: &[f32]| send_block(&mut producer, input),
+            forward_stream_error(self.errors.clone(), JAudioStream::Microphone, self.generation)
+        ).map_err(|e| format!("Error building input stream {e} :("))?;
+        stream.play().map_err(|e| format!("Error starting input stream {e} :("))?;
+        if !self.send(EngineCommand::SetMicrophone(Some(ChannelMapper::new(reader, self.channels)))) {
+            return Err(String::from("Audio engine command queue is full"));
+        }
+        self.microphone = Some(stream);
+        Ok(())
+    }
+}
@@ -0,0 +1,78 @@
+use crate::pitch::JPlayback;
+use crate::source::JFrameSource;
+
+
+#[inline]
+pub fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+#[inline]
+pub fn gain_to_db(gain: f32) -> f32 {
+    20.0 * gain.max(1e-10).log10()
+}
+
+
+/// Left and right gain of a balance pan, the center position leave both side untouched.
+pub fn pan_gains(pan: f32) -> (f32, f32) {
+    let pan = pan.clamp(-1.0, 1.0);
+    let attenuation = (pan.abs() * std::f32::consts::FRAC_PI_2).cos();
+    if pan > 0.0 {
+        (attenuation, 1.0)
+    } else {
+        (1.0, attenuation)
+    }
+}
+
+
+/// Apply a gain to every channel and pan the first two channels.
+pub struct GainPan<S: JFrameSource> {
+    source: S,
+    gains: Vec<f32>
+}
+
+impl<S: JFrameSource> GainPan<S> {
+
+    pub fn new(source: S, gain: f32, pan: f32) -> Self {
+        let mut gains = vec![gain; source.channels()];
+        if gains.len() >= 2 {
+            let (left, right) = pan_gains(pan);
+            gains[0] *= left;
+            gains[1] *= right;
+        }
+        GainPan { source, gains }
+    }
+}
+
+impl<S: JFrameSource> JFrameSource for GainPan<S> {
+
+    fn channels(&self) -> usize {
+        self.gains.len()
+    }
+
+    fn next_frame(&mut self, frame: &mut [f32]) -> bool {
+        if !self.source.next_frame(frame) {
+            return false;
+        }
+        for (sample, gain) in frame.iter_mut().zip(self.gains.iter()) {
+            *sample *= gain;
+        }
+        true
+    }
+
+    fn seek(&mut self, frame: usize) -> bool {
+        self.source.seek(frame)
+    }
+
+    fn position(&self) -> Option<usize> {
+        self.source.position()
+    }
+
+    fn set_playback(&mut self, playback: JPlayback) -> bool {
+        self.source.set_playback(playback)
+    }
+
+    fn playback_rate(&self) -> f64 {
+        self.source.playback_rate()
+    }
+}
@@ -0,0 +1,64 @@
+use cpal::{Device, FromSample, Sample, SampleFormat, SizedSample, StreamConfig, SupportedStreamConfig};
+use cpal::traits::DeviceTrait;
+
+use crate::output::block_buffer;
+
+
+/// Build an input stream for any sample format supported by cpal.
+/// `capture` always receive an interleaved f32 buffer, it is converted from the device format first.
+/// `on_error` is called when the stream fails, a lost device for instance.
+pub fn build_input_stream<C, E>(
+    device: &Device,
+    config: &SupportedStreamConfig,
+    capture: C,
+    on_error: E
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    C: FnMut(&[f32]) + Send + 'static,
+    E: FnMut(cpal::StreamError) + Send + 'static
+{
+    let stream_config: StreamConfig = config.config();
+    match config.sample_format() {
+        SampleFormat::I8 => build_typed_input_stream::<i8, C, E>(device, &stream_config, capture, on_error),
+        SampleFormat::I16 => build_typed_input_stream::<i16, C, E>(device, &stream_config, capture, on_error),
+        SampleFormat::I32 => build_typed_input_stream::<i32, C, E>(device, &stream_config, capture, on_error),
+        SampleFormat::I64 => build_typed_input_stream::<i64, C, E>(device, &stream_config, capture, on_error),
+        SampleFormat::U8 => build_typed_input_stream::<u8, C, E>(device, &stream_config, capture, on_error),
+        SampleFormat::U16 => build_typed_input_stream::<u16, C, E>(device, &stream_config, capture, on_error),
+        SampleFormat::U32 => build_typed_input_stream::<u32, C, E>(device, &stream_config, capture, on_error),
+        SampleFormat::U64 => build_typed_input_stream::<u64, C, E>(device, &stream_config, capture, on_error),
+        SampleFormat::F32 => build_typed_input_stream::<f32, C, E>(device, &stream_config, capture, on_error),
+        SampleFormat::F64 => build_typed_input_stream::<f64, C, E>(device, &stream_config, capture, on_error),
+        _ => Err(cpal::BuildStreamError::StreamConfigNotSupported)
+    }
+}
+
+
+fn build_typed_input_stream<T, C, E>(
+    device: &Device,
+    config: &StreamConfig,
+    mut capture: C,
+    on_error: E
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+    C: FnMut(&[f32]) + Send + 'static,
+    E: FnMut(cpal::StreamError) + Send + 'static
+{
+    let mut samples = block_buffer(config);
+    device.build_input_stream(
+        config,
+        move |input: &[T], _info| {
+            for input in input.chunks(samples.len()) {
+                let samples = &mut samples[..input.len()];
+                for (sample, captured) in samples.iter_mut().zip(input.iter()) {
+                    *sample = f32::from_sample(*captured);
+                }
+                capture(samples);
+            }
+        },
+        on_error,
+        None
+    )
+}
@@ -1,7 +1,38 @@
 mod global_hotkey;
 mod app;
 mod app_ui;
+mod bridge;
+mod channel_map;
+mod decoder;
+mod ducking;
+mod effects;
+mod engine;
+mod gain;
+mod input;
+mod loudness;
+mod mixer;
+mod output;
+mod pitch;
+mod recorder;
+mod resampler;
+mod sequence;
+mod smoothed;
+mod sound;
+mod source;
+mod trigger;
 
 pub use global_hotkey::{JGlobalHotkey, JGlobalHotkeyErrors, JGlobalHotkeyManager, JGlobalHotKeyEvent};
-pub use app::{JApp, JMouseButton};
+pub use app::{JApp, JAppEvent, JMouseButton, JPlaybackEvent, SoundId};
 pub use app_ui::JAppUI;
+pub use decoder::{JSoundErrors, JSoundFormat};
+pub use ducking::{JDucking, JDuckingMode};
+pub use engine::JAudioStream;
+pub use effects::{JCompressor, JEffect, JEqBand, JFilterType, JLimiter, JReverb};
+pub use loudness::JLoudness;
+pub use mixer::{JVoiceEvent, JVoiceStealPolicy, VoiceId};
+pub use pitch::JPlayback;
+pub use recorder::{JRecordErrors, JRecording};
+pub use resampler::JResampleQuality;
+pub use sequence::{JSequence, JSequenceStep, SequenceId};
+pub use sound::{JLoopPoints, JLoudnessCache, JNormalize, JSoundEntry};
+pub use trigger::{JPoolOrder, JTransportAction, JTriggerMode};
@@ -0,0 +1,277 @@
+use serde::{Serialize, Deserialize};
+
+use crate::gain::gain_to_db;
+
+
+// the loudness of a silent sound, also the absolute gate of the measure
+const SILENCE_LUFS: f64 = -70.0;
+const SUB_BLOCK_SECONDS: f64 = 0.1;
+// momentary blocks are 400ms and short term blocks 3s long, both with a 100ms hop
+const MOMENTARY_SUB_BLOCKS: usize = 4;
+const SHORT_TERM_SUB_BLOCKS: usize = 30;
+const TRUE_PEAK_HALF_TAPS: usize = 6;
+
+
+/// EBU R128 measure of a sound.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct JLoudness {
+    /// integrated loudness in LUFS.
+    pub integrated: f32,
+    /// loudness range in LU.
+    pub range: f32,
+    /// true peak in dBTP.
+    pub true_peak: f32
+}
+
+
+/// Biquad direct form 1 coefficients, `a0` is normalized to 1.
+pub struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2]
+}
+
+impl Biquad {
+
+    pub fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Biquad { b, a, x: [0.0; 2], y: [0.0; 2] }
+    }
+
+    /// Change the response but keep the filter state.
+    pub fn set_coefficients(&mut self, b: [f64; 3], a: [f64; 2]) {
+        self.b = b;
+        self.a = a;
+    }
+
+    #[inline]
+    pub fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0] - self.a[1] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+}
+
+
+/// The two stages of the BS.1770 K-weighting filter for any sample rate.
+fn k_weighting(sample_rate: u32) -> (Biquad, Biquad) {
+    let fs = sample_rate as f64;
+
+    // high shelf modeling the acoustic effect of the head
+    let f0 = 1681.974450955533;
+    let gain = 3.999843853973347;
+    let q = 0.7071752369554196;
+    let k = (std::f64::consts::PI * f0 / fs).tan();
+    let vh = 10f64.powf(gain / 20.0);
+    let vb = vh.powf(0.4996667741545416);
+    let a0 = 1.0 + k / q + k * k;
+    let shelf = Biquad::new(
+        [(vh + vb * k / q + k * k) / a0, 2.0 * (k * k - vh) / a0, (vh - vb * k / q + k * k) / a0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0]
+    );
+
+    // revised low frequency B-curve high pass
+    let f0 = 38.13547087602444;
+    let q = 0.5003270373238773;
+    let k = (std::f64::consts::PI * f0 / fs).tan();
+    let a0 = 1.0 + k / q + k * k;
+    let high_pass = Biquad::new(
+        [1.0, -2.0, 1.0],
+        [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0]
+    );
+    (shelf, high_pass)
+}
+
+
+/// BS.1770 channel weight, assuming the wav / vorbis channel order.
+fn channel_weight(channel: usize, channels: usize) -> f64 {
+    match (channels, channel) {
+        // lfe isn't part of the measure
+        (6, 3) | (8, 3) => 0.0,
+        // surround channels
+        (5, 3) | (5, 4) | (6, 4) | (6, 5) | (8, 4..=7) => 1.41,
+        _ => 1.0
+    }
+}
+
+
+fn energy_to_lufs(energy: f64) -> f64 {
+    if energy <= 0.0 {
+        return f64::NEG_INFINITY;
+    }
+    -0.691 + 10.0 * energy.log10()
+}
+
+
+/// Mean energy of every window of `len` sub blocks, moving one sub block at the time.
+fn block_energies(sub_blocks: &[f64], sub_block_len: usize, len: usize) -> Vec<f64> {
+    if sub_blocks.len() < len {
+        return Vec::new();
+    }
+    sub_blocks
+        .windows(len)
+        .map(|w| w.iter().sum::<f64>() / (len * sub_block_len) as f64)
+        .collect()
+}
+
+
+fn integrated_loudness(blocks: &[f64]) -> f64 {
+    let gated: Vec<f64> = blocks.iter().copied().filter(|e| energy_to_lufs(*e) > SILENCE_LUFS).collect();
+    if gated.is_empty() {
+        return SILENCE_LUFS;
+    }
+    let relative_gate = energy_to_lufs(gated.iter().sum::<f64>() / gated.len() as f64) - 10.0;
+    let gated: Vec<f64> = gated.into_iter().filter(|e| energy_to_lufs(*e) > relative_gate).collect();
+    if gated.is_empty() {
+        return SILENCE_LUFS;
+    }
+    energy_to_lufs(gated.iter().sum::<f64>() / gated.len() as f64).max(SILENCE_LUFS)
+}
+
+
+/// EBU Tech 3342 loudness range.
+fn loudness_range(blocks: &[f64]) -> f64 {
+    let gated: Vec<f64> = blocks.iter().copied().filter(|e| energy_to_lufs(*e) > SILENCE_LUFS).collect();
+    if gated.len() < 2 {
+        return 0.0;
+    }
+    let relative_gate = energy_to_lufs(gated.iter().sum::<f64>() / gated.len() as f64) - 20.0;
+    let mut loudness: Vec<f64> = gated
+        .into_iter()
+        .map(energy_to_lufs)
+        .filter(|l| *l > relative_gate)
+        .collect();
+    if loudness.len() < 2 {
+        return 0.0;
+    }
+    loudness.sort_by(|a, b| a.total_cmp(b));
+    let percentile = |p: f64| loudness[((loudness.len() - 1) as f64 * p).round() as usize];
+    percentile(0.95) - percentile(0.10)
+}
+
+
+/// Highest peak of the signal oversampled to at least 192kHz.
+fn true_peak(data: &[f32], channels: usize, sample_rate: u32) -> f32 {
+    let oversampling = match sample_rate {
+        0..=95_999 => 4,
+        96_000..=191_999 => 2,
+        _ => 1
+    };
+    let mut peak = data.iter().fold(0.0f32, |p, s| p.max(s.abs()));
+    if oversampling == 1 {
+        return peak;
+    }
+
+    // hann windowed sinc for each intermediate phase
+    let phases: Vec<Vec<f32>> = (1..oversampling)
+        .map(|phase| {
+            let offset = phase as f64 / oversampling as f64;
+            (0..2 * TRUE_PEAK_HALF_TAPS)
+                .map(|i| {
+                    let t = i as f64 - (TRUE_PEAK_HALF_TAPS as f64 - 1.0) - offset;
+                    let x = std::f64::consts::PI * t;
+                    let sinc = if x == 0.0 { 1.0 } else { x.sin() / x };
+                    let window = 0.5 + 0.5 * (std::f64::consts::PI * t / TRUE_PEAK_HALF_TAPS as f64).cos();
+                    (sinc * window) as f32
+                })
+                .collect()
+        })
+        .collect();
+
+    let frames = data.len() / channels;
+    for channel in 0..channels {
+        let sample = |frame: isize| -> f32 {
+            if frame < 0 || frame as usize >= frames {
+                return 0.0;
+            }
+            data[frame as usize * channels + channel]
+        };
+        for frame in 0..frames as isize {
+            for taps in phases.iter() {
+                let value: f32 = taps
+                    .iter()
+                    .enumerate()
+                    .map(|(i, h)| h * sample(frame + i as isize - (TRUE_PEAK_HALF_TAPS as isize - 1)))
+                    .sum();
+                peak = peak.max(value.abs());
+            }
+        }
+    }
+    peak
+}
+
+
+/// Measure the integrated loudness, loudness range and true peak of an interleaved sound.
+pub fn analyze(data: &[f32], channels: usize, sample_rate: u32) -> JLoudness {
+    let channels = channels.max(1);
+    let sub_block_len = ((sample_rate as f64 * SUB_BLOCK_SECONDS) as usize).max(1);
+
+    let mut filters: Vec<(Biquad, Biquad)> = (0..channels).map(|_| k_weighting(sample_rate)).collect();
+    let weights: Vec<f64> = (0..channels).map(|c| channel_weight(c, channels)).collect();
+    let mut sub_blocks = Vec::with_capacity(data.len() / channels / sub_block_len + 1);
+    let mut current = 0.0;
+    let mut total = 0.0;
+    for (i, frame) in data.chunks_exact(channels).enumerate() {
+        for ((sample, (shelf, high_pass)), weight) in frame.iter().zip(filters.iter_mut()).zip(weights.iter()) {
+            let filtered = high_pass.process(shelf.process(*sample as f64));
+            current += weight * filtered * filtered;
+        }
+        if (i + 1) % sub_block_len == 0 {
+            sub_blocks.push(current);
+            total += current;
+            current = 0.0;
+        }
+    }
+    total += current;
+
+    let momentary = block_energies(&sub_blocks, sub_block_len, MOMENTARY_SUB_BLOCKS);
+    let integrated = if momentary.is_empty() {
+        // sound effects are often shorter than a single 400ms block, measure them as a whole
+        let frames = (data.len() / channels).max(1);
+        integrated_loudness(&[total / frames as f64])
+    } else {
+        integrated_loudness(&momentary)
+    };
+    let short_term = block_energies(&sub_blocks, sub_block_len, SHORT_TERM_SUB_BLOCKS);
+
+    JLoudness {
+        integrated: integrated as f32,
+        range: loudness_range(&short_term) as f32,
+        true_peak: gain_to_db(true_peak(data, channels, sample_rate))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::analyze;
+
+    fn stereo_sine(frequency: f32, amplitude: f32, seconds: f32, sample_rate: u32) -> Vec<f32> {
+        let frames = (seconds * sample_rate as f32) as usize;
+        (0..frames)
+            .flat_map(|i| {
+                let value = amplitude * (2.0 * std::f32::consts::PI * frequency * i as f32 / sample_rate as f32).sin();
+                [value, value]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn sine_loudness_test() {
+        // EBU tech 3341: a -23 dBFS 1kHz stereo sine reads -23 LUFS
+        let data = stereo_sine(1000.0, 10f32.powf(-23.0 / 20.0), 5.0, 48000);
+        let loudness = analyze(&data, 2, 48000);
+        assert!((loudness.integrated + 23.0).abs() < 0.1, "{loudness:?}");
+        assert!(loudness.range.abs() < 0.1, "{loudness:?}");
+        assert!((loudness.true_peak + 23.0).abs() < 0.1, "{loudness:?}");
+    }
+
+    #[test]
+    fn silence_loudness_test() {
+        let loudness = analyze(&vec![0.0; 48000], 1, 48000);
+        assert_eq!(loudness.integrated, -70.0);
+        assert_eq!(loudness.range, 0.0);
+    }
+}
@@ -7,7 +7,8 @@ use winittray::{
     JGlobalHotkeyManager,
     JApp,
     JMouseButton,
-    JAppUI
+    JAppUI,
+    JTriggerMode
 };
 
 use image;
@@ -64,10 +65,12 @@ fn main() {
     app.with_mouse_input_on_play(JMouseButton::MouseButton5);
 
     let sound_id = app.load_sound(concat!(env!("CARGO_MANIFEST_DIR"), "/resources/chiguibigoula.wav")).unwrap();
-    app.register_hoktey_for_sound("ALT+P", sound_id).unwrap();
+    app.register_hoktey_for_sound("ALT+P", sound_id, JTriggerMode::OneShot).unwrap();
 
     let sound_id = app.load_sound(concat!(env!("CARGO_MANIFEST_DIR"), "/resources/wiggle.wav")).unwrap();
-    app.register_hoktey_for_sound("ALT+O", sound_id).unwrap();
+    app.register_hoktey_for_sound("ALT+O", sound_id, JTriggerMode::OneShot).unwrap();
+
+    app.register_hotkey_for_recording("ALT+R", JTriggerMode::Toggle).unwrap();
 
     let path = concat!(env!("CARGO_MANIFEST_DIR"), "/resources/icon.png");
     let icon = load_icon(std::path::Path::new(path));
@@ -83,7 +86,7 @@ fn main() {
 
     let menu_channel = MenuEvent::receiver();
     // let tray_channel = TrayIconEvent::receiver();
-    let mut app_ui = JAppUI::new();
+    let mut app_ui = JAppUI::new(app.event_sender());
     let backend = i_slint_backend_winit::BackendBuilder::new()
         .with_quit_on_last_window(false)
         .with_msg_hook(|msg| JGlobalHotkeyManager::process_msg(msg))
@@ -101,6 +104,7 @@ fn main() {
                                 if let Some(device) = app.state.current_device.as_ref() {
                                     app_ui.set_current_device(device);
                                 }
+                                app_ui.set_volume(app.state.volume);
                             }
                         },
                         TrayAction::Close => {
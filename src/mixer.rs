@@ -0,0 +1,766 @@
+use serde::{Serialize, Deserialize};
+
+use crate::effects::EffectChain;
+use crate::pitch::JPlayback;
+use crate::smoothed::SmoothedValue;
+use crate::source::JFrameSource;
+
+
+// time taken by a gain change to reach its target
+const GAIN_RAMP_SECONDS: f32 = 0.02;
+// shortest fade out of a stopped voice, anything shorter click
+const MIN_RELEASE_SECONDS: f32 = 0.005;
+const PROGRESS_INTERVAL_SECONDS: f32 = 0.1;
+// hard limits so the audio thread never allocates, voices fading out count too
+const VOICE_CAPACITY: usize = 256;
+const SCHEDULED_CAPACITY: usize = 1024;
+// every voice can start and end in the same block
+const EVENT_CAPACITY: usize = 2 * (VOICE_CAPACITY + SCHEDULED_CAPACITY);
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VoiceId(pub(crate) usize);
+
+
+/// One play of a sequence, it owns the stops the sequence schedules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SequenceRunId(pub(crate) usize);
+
+
+/// What to do when a sound is triggered while every voice is in use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum JVoiceStealPolicy {
+    /// stop the voice that started first.
+    #[default]
+    Oldest,
+    /// stop the voice with the lowest level.
+    Quietest,
+    /// don't play the new sound.
+    Reject
+}
+
+
+/// Change in the life of a voice, reported by the mixer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JVoiceEvent {
+    Started,
+    /// frames of the sound played so far counted at the output rate, sent a few times per second.
+    Progress(usize),
+    /// the voice played until its end.
+    Finished,
+    /// the voice was stopped before its end, or never started.
+    Stopped
+}
+
+
+/// Fade in and fade out length of a voice in frames.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct VoiceFades {
+    pub fade_in: usize,
+    pub fade_out: usize
+}
+
+
+struct Envelope {
+    gain: f32,
+    attack_step: f32,
+    release_len: usize,
+    // set once the voice is stopped
+    release_step: Option<f32>
+}
+
+impl Envelope {
+
+    fn new(fades: VoiceFades, min_release: usize) -> Self {
+        Envelope {
+            gain: if fades.fade_in == 0 { 1.0 } else { 0.0 },
+            attack_step: 1.0 / fades.fade_in.max(1) as f32,
+            release_len: fades.fade_out.max(min_release).max(1),
+            release_step: None
+        }
+    }
+
+    fn release(&mut self, len: usize) {
+        if self.release_step.is_none() {
+            self.release_step = Some(self.gain / len.max(1) as f32);
+        }
+    }
+
+    fn is_released(&self) -> bool {
+        self.release_step.is_some()
+    }
+
+    #[inline]
+    fn next_value(&mut self) -> f32 {
+        let gain = self.gain;
+        match self.release_step {
+            Some(step) => self.gain = (self.gain - step).max(0.0),
+            None => self.gain = (self.gain + self.attack_step).min(1.0)
+        };
+        gain
+    }
+
+    fn is_silent(&self) -> bool {
+        self.is_released() && self.gain <= 0.0
+    }
+}
+
+
+struct Voice {
+    id: VoiceId,
+    source: Box<dyn JFrameSource>,
+    envelope: Envelope,
+    // dips to silence while the voice pauses or seeks
+    transport_gain: SmoothedValue,
+    paused: bool,
+    // frame of the sound and played position to jump to once silent
+    seek_to: Option<(usize, f64)>,
+    choke_group: Option<u32>,
+    // peak of the last rendered block, used to find the quietest voice
+    level: f32,
+    // position in the sound at the output rate, it moves faster when the sound is sped up
+    played: f64,
+    done: bool
+}
+
+
+/// Something happening a number of frames after it was scheduled.
+enum Scheduled {
+    Voice {
+        id: VoiceId,
+        source: Box<dyn JFrameSource>,
+        fades: VoiceFades,
+        choke_group: Option<u32>
+    },
+    /// stop of a sequence, cancelled with its run.
+    StopAll(SequenceRunId)
+}
+
+
+/// Sum every active voice in the output buffer.
+pub struct Mixer {
+    channels: usize,
+    voices: Vec<Voice>,
+    // kept in scheduling order, events due on the same frame happen in that order
+    scheduled: Vec<(usize, Scheduled)>,
+    max_voices: usize,
+    steal_policy: JVoiceStealPolicy,
+    master_gain: SmoothedValue,
+    master_effects: EffectChain,
+    min_release: usize,
+    progress_interval: usize,
+    events: Vec<(VoiceId, JVoiceEvent)>,
+    // sources of the removed voices, freeing them is left to another thread
+    dead: Vec<Box<dyn JFrameSource>>,
+    frame: Vec<f32>
+}
+
+impl Mixer {
+
+    pub fn new(channels: usize, sample_rate: u32, max_voices: usize, steal_policy: JVoiceStealPolicy) -> Self {
+        Mixer {
+            channels,
+            voices: Vec::with_capacity(VOICE_CAPACITY),
+            scheduled: Vec::with_capacity(SCHEDULED_CAPACITY),
+            max_voices: max_voices.clamp(1, VOICE_CAPACITY),
+            steal_policy,
+            master_gain: SmoothedValue::new(1.0, (sample_rate as f32 * GAIN_RAMP_SECONDS) as usize),
+            master_effects: EffectChain::new(&[], channels, sample_rate),
+            min_release: (sample_rate as f32 * MIN_RELEASE_SECONDS) as usize,
+            progress_interval: ((sample_rate as f32 * PROGRESS_INTERVAL_SECONDS) as usize).max(1),
+            events: Vec::with_capacity(EVENT_CAPACITY),
+            dead: Vec::with_capacity(VOICE_CAPACITY + SCHEDULED_CAPACITY),
+            frame: vec![0.0; channels]
+        }
+    }
+
+    pub fn set_master_gain(&mut self, gain: f32) {
+        // nothing can click while the mixer is silent
+        if self.is_idle() {
+            self.master_gain.set_immediate(gain.max(0.0));
+        } else {
+            self.master_gain.set_target(gain.max(0.0));
+        }
+    }
+
+    /// Effects applied after the master gain.
+    /// Return the replaced chain, to drop outside the audio thread.
+    pub fn set_master_effects(&mut self, chain: EffectChain) -> EffectChain {
+        self.master_effects.replace(chain)
+    }
+
+    pub fn set_max_voices(&mut self, max_voices: usize) {
+        self.max_voices = max_voices.clamp(1, VOICE_CAPACITY);
+        while self.playing_voices() > self.max_voices {
+            self.steal_voice(JVoiceStealPolicy::Oldest);
+        }
+    }
+
+    pub fn set_steal_policy(&mut self, steal_policy: JVoiceStealPolicy) {
+        self.steal_policy = steal_policy;
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.voices.is_empty() && self.scheduled.is_empty()
+    }
+
+    /// True while a started voice can be heard, fade outs included, scheduled voices don't count.
+    pub fn is_sounding(&self) -> bool {
+        self.voices.iter().any(|v| !v.paused || v.transport_gain.value() > 0.0)
+    }
+
+    /// Number of voices that are not fading out.
+    fn playing_voices(&self) -> usize {
+        self.voices.iter().filter(|v| !v.envelope.is_released()).count()
+    }
+
+    fn steal_voice(&mut self, steal_policy: JVoiceStealPolicy) {
+        // voices are kept in start order
+        let mut playing = self.voices.iter_mut().filter(|v| !v.envelope.is_released());
+        let stolen = match steal_policy {
+            JVoiceStealPolicy::Quietest => playing.min_by(|a, b| a.level.total_cmp(&b.level)),
+            _ => playing.next()
+        };
+        if let Some(voice) = stolen {
+            voice.envelope.release(self.min_release);
+        }
+    }
+
+    /// Add a voice to the mix, return false when it was rejected by the steal policy or the voices are full.
+    /// The voice cuts every other voice of its choke group.
+    pub fn add_voice(&mut self, id: VoiceId, source: Box<dyn JFrameSource>, fades: VoiceFades, choke_group: Option<u32>) -> bool {
+        if let Some(group) = choke_group {
+            for voice in self.voices.iter_mut().filter(|v| v.choke_group == Some(group)) {
+                voice.envelope.release(voice.envelope.release_len);
+            }
+        }
+        let playing = self.playing_voices();
+        if self.voices.len() >= VOICE_CAPACITY || (playing >= self.max_voices && self.steal_policy == JVoiceStealPolicy::Reject) {
+            self.reject(id, source);
+            return false;
+        }
+        if playing >= self.max_voices {
+            self.steal_voice(self.steal_policy);
+        }
+        self.voices.push(Voice {
+            id,
+            source,
+            envelope: Envelope::new(fades, self.min_release),
+            transport_gain: SmoothedValue::new(1.0, self.min_release),
+            paused: false,
+            seek_to: None,
+            choke_group,
+            level: 0.0,
+            played: 0.0,
+            done: false
+        });
+        push_bounded(&mut self.events, (id, JVoiceEvent::Started));
+        true
+    }
+
+    fn reject(&mut self, id: VoiceId, source: Box<dyn JFrameSource>) {
+        push_bounded(&mut self.events, (id, JVoiceEvent::Stopped));
+        push_bounded(&mut self.dead, source);
+    }
+
+    /// Start a voice `delay` frames into the next rendered block, it is stopped right away when too many things are scheduled.
+    pub fn schedule_voice(
+        &mut self,
+        delay: usize,
+        id: VoiceId,
+        source: Box<dyn JFrameSource>,
+        fades: VoiceFades,
+        choke_group: Option<u32>
+    ) {
+        if self.scheduled.len() >= SCHEDULED_CAPACITY {
+            self.reject(id, source);
+            return;
+        }
+        self.scheduled.push((delay, Scheduled::Voice { id, source, fades, choke_group }));
+    }
+
+    /// Stop every voice started before `delay` frames into the next rendered block.
+    /// Cancelling `owner`, the run of the sequence asking for it, cancels it.
+    pub fn schedule_stop_all(&mut self, delay: usize, owner: SequenceRunId) {
+        // nothing left to stop when no voice could be scheduled
+        if self.scheduled.len() < SCHEDULED_CAPACITY {
+            self.scheduled.push((delay, Scheduled::StopAll(owner)));
+        }
+    }
+
+    /// A voice is active until it ends or is stopped, scheduled voices are active.
+    // the app follows the voices through their events, only the tests ask the mixer
+    #[cfg(test)]
+    pub fn is_voice_active(&self, id: VoiceId) -> bool {
+        self.voices.iter().any(|v| v.id == id && !v.envelope.is_released())
+            || self.scheduled.iter().any(|(_, s)| matches!(s, Scheduled::Voice { id: v, .. } if *v == id))
+    }
+
+    /// Fade out a voice, it is removed once silent.
+    pub fn stop_voice(&mut self, id: VoiceId) {
+        for voice in self.voices.iter_mut().filter(|v| v.id == id) {
+            voice.envelope.release(voice.envelope.release_len);
+        }
+        self.cancel_scheduled(Some(id));
+    }
+
+    /// Pause a voice, it resumes from the frame it was paused on.
+    pub fn pause_voice(&mut self, id: VoiceId) {
+        for voice in self.voices.iter_mut().filter(|v| v.id == id) {
+            // the frames pulled during the fade out are played again on resume
+            if voice.seek_to.is_none() {
+                voice.seek_to = voice.source.position().map(|frame| (frame, voice.played));
+            }
+            voice.paused = true;
+            voice.transport_gain.set_target(0.0);
+        }
+    }
+
+    pub fn resume_voice(&mut self, id: VoiceId) {
+        for voice in self.voices.iter_mut().filter(|v| v.id == id) {
+            voice.paused = false;
+            // a voice resumed during its fade out jumps back first
+            if voice.seek_to.is_none() {
+                voice.transport_gain.set_target(1.0);
+            }
+        }
+    }
+
+    #[cfg(test)]
+    pub fn is_voice_paused(&self, id: VoiceId) -> bool {
+        self.voices.iter().any(|v| v.id == id && v.paused)
+    }
+
+    /// Move a voice to a `frame` of its sound, `played` is the same position counted at the output rate.
+    pub fn seek_voice(&mut self, id: VoiceId, frame: usize, played: usize) {
+        for voice in self.voices.iter_mut().filter(|v| v.id == id) {
+            // the jump happens once the voice faded out
+            voice.seek_to = Some((frame, played as f64));
+            voice.transport_gain.set_target(0.0);
+        }
+    }
+
+    pub fn set_voice_playback(&mut self, id: VoiceId, playback: JPlayback) {
+        for voice in self.voices.iter_mut().filter(|v| v.id == id) {
+            voice.source.set_playback(playback);
+        }
+        for (_, scheduled) in self.scheduled.iter_mut() {
+            if let Scheduled::Voice { id: voice_id, source, .. } = scheduled {
+                if *voice_id == id {
+                    source.set_playback(playback);
+                }
+            }
+        }
+    }
+
+    /// Return the replaced chain, or `chain` when there is no such voice, to drop outside the audio thread.
+    pub fn set_voice_effects(&mut self, id: VoiceId, chain: EffectChain) -> EffectChain {
+        if let Some(voice) = self.voices.iter_mut().find(|v| v.id == id) {
+            return voice.source.set_effects(chain);
+        }
+        for (_, scheduled) in self.scheduled.iter_mut() {
+            if let Scheduled::Voice { id: voice_id, source, .. } = scheduled {
+                if *voice_id == id {
+                    return source.set_effects(chain);
+                }
+            }
+        }
+        chain
+    }
+
+    /// Fade out every voice and cancel everything scheduled.
+    pub fn stop_all(&mut self) {
+        self.release_all();
+        self.cancel_scheduled(None);
+    }
+
+    /// Cancel the stops a sequence run scheduled, the voices it plays are stopped one by one.
+    pub fn cancel_sequence_run(&mut self, run: SequenceRunId) {
+        self.scheduled.retain(|(_, s)| !matches!(s, Scheduled::StopAll(owner) if *owner == run));
+    }
+
+    /// Cancel a scheduled voice, or everything scheduled when `id` is none.
+    fn cancel_scheduled(&mut self, id: Option<VoiceId>) {
+        let mut index = 0;
+        while index < self.scheduled.len() {
+            let cancel = match &self.scheduled[index].1 {
+                Scheduled::Voice { id: voice_id, .. } => id.is_none() || id == Some(*voice_id),
+                Scheduled::StopAll(_) => id.is_none()
+            };
+            if !cancel {
+                index += 1;
+                continue;
+            }
+            if let Scheduled::Voice { id, source, .. } = self.scheduled.remove(index).1 {
+                self.reject(id, source);
+            }
+        }
+    }
+
+    /// Events since the last call, oldest first.
+    pub fn drain_events(&mut self) -> std::vec::Drain<'_, (VoiceId, JVoiceEvent)> {
+        self.events.drain(..)
+    }
+
+    /// Sources of the voices removed since the last call, to drop outside the audio thread.
+    pub fn drain_dead(&mut self) -> std::vec::Drain<'_, Box<dyn JFrameSource>> {
+        self.dead.drain(..)
+    }
+
+    fn release_all(&mut self) {
+        for voice in self.voices.iter_mut() {
+            voice.envelope.release(voice.envelope.release_len);
+        }
+    }
+
+    /// Run the events due now.
+    fn run_scheduled(&mut self) {
+        while let Some(index) = self.scheduled.iter().position(|(delay, _)| *delay == 0) {
+            match self.scheduled.remove(index).1 {
+                Scheduled::Voice { id, source, fades, choke_group } => {
+                    self.add_voice(id, source, fades, choke_group);
+                },
+                Scheduled::StopAll(_) => self.release_all()
+            }
+        }
+    }
+
+    pub fn render(&mut self, output: &mut [f32]) {
+        output.fill(0.0);
+        let frames = output.len() / self.channels;
+        let mut position = 0;
+        loop {
+            self.run_scheduled();
+            // render until the next scheduled event so it happens on its exact frame
+            let len = self.scheduled
+                .iter()
+                .map(|(delay, _)| *delay)
+                .min()
+                .unwrap_or(usize::MAX)
+                .min(frames - position);
+            self.render_voices(&mut output[position * self.channels..(position + len) * self.channels]);
+            self.scheduled.iter_mut().for_each(|(delay, _)| *delay -= len);
+            position += len;
+            if position == frames {
+                break;
+            }
+        }
+
+        for out_frame in output.chunks_mut(self.channels) {
+            let gain = self.master_gain.next_value();
+            out_frame.iter_mut().for_each(|s| *s *= gain);
+            self.master_effects.process(out_frame);
+        }
+    }
+
+    fn render_voices(&mut self, output: &mut [f32]) {
+        for voice in self.voices.iter_mut() {
+            let mut peak: f32 = 0.0;
+            let played = voice.played as usize;
+            // the speed only changes between blocks
+            let rate = voice.source.playback_rate();
+            for out_frame in output.chunks_mut(self.channels) {
+                if voice.transport_gain.value() == 0.0 && (voice.paused || voice.seek_to.is_some()) {
+                    if let Some((frame, played)) = voice.seek_to.take() {
+                        voice.source.seek(frame);
+                        voice.played = played;
+                    }
+                    if voice.paused {
+                        // a paused voice stopped by the user is already silent
+                        voice.done = voice.envelope.is_released();
+                        break;
+                    }
+                    voice.transport_gain.set_target(1.0);
+                }
+                if voice.envelope.is_silent() || !voice.source.next_frame(&mut self.frame) {
+                    voice.done = true;
+                    break;
+                }
+                let gain = voice.envelope.next_value() * voice.transport_gain.next_value();
+                for (out, sample) in out_frame.iter_mut().zip(self.frame.iter()) {
+                    *out += sample * gain;
+                    peak = peak.max((sample * gain).abs());
+                }
+                voice.played += rate;
+            }
+            voice.level = peak;
+
+            if voice.done {
+                let event = if voice.envelope.is_released() { JVoiceEvent::Stopped } else { JVoiceEvent::Finished };
+                push_bounded(&mut self.events, (voice.id, event));
+            } else if voice.played as usize / self.progress_interval != played / self.progress_interval {
+                push_bounded(&mut self.events, (voice.id, JVoiceEvent::Progress(voice.played as usize)));
+            }
+        }
+
+        // removed in place to keep the start order
+        let mut index = 0;
+        while index < self.voices.len() {
+            if self.voices[index].done {
+                let voice = self.voices.remove(index);
+                push_bounded(&mut self.dead, voice.source);
+            } else {
+                index += 1;
+            }
+        }
+    }
+}
+
+
+/// Push without growing the vector, the item is dropped when it is full.
+fn push_bounded<T>(vec: &mut Vec<T>, item: T) {
+    if vec.len() < vec.capacity() {
+        vec.push(item);
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{JVoiceEvent, JVoiceStealPolicy, Mixer, SequenceRunId, VoiceFades, VoiceId};
+    use crate::source::JFrameSource;
+
+    struct Constant {
+        value: f32,
+        len: usize
+    }
+
+    impl JFrameSource for Constant {
+
+        fn channels(&self) -> usize {
+            1
+        }
+
+        fn next_frame(&mut self, frame: &mut [f32]) -> bool {
+            if self.len == 0 {
+                return false;
+            }
+            self.len -= 1;
+            frame[0] = self.value;
+            true
+        }
+    }
+
+    fn constant(value: f32, len: usize) -> Box<dyn JFrameSource> {
+        Box::new(Constant { value, len })
+    }
+
+    #[test]
+    fn mix_voices_test() {
+        let mut mixer = Mixer::new(1, 48000, 4, JVoiceStealPolicy::Oldest);
+        assert!(mixer.add_voice(VoiceId(0), constant(0.25, 4), VoiceFades::default(), None));
+        assert!(mixer.add_voice(VoiceId(1), constant(0.5, 2), VoiceFades::default(), None));
+
+        let mut output = [0.0; 3];
+        mixer.render(&mut output);
+        assert_eq!(output, [0.75, 0.75, 0.25]);
+        mixer.render(&mut output);
+        assert_eq!(output, [0.25, 0.0, 0.0]);
+        assert!(mixer.is_idle());
+    }
+
+    #[test]
+    fn steal_policy_test() {
+        let mut output = [0.0; 1];
+
+        // 1 sample minimum release
+        let mut mixer = Mixer::new(1, 200, 2, JVoiceStealPolicy::Oldest);
+        mixer.add_voice(VoiceId(0), constant(0.1, 10), VoiceFades::default(), None);
+        mixer.add_voice(VoiceId(1), constant(0.2, 10), VoiceFades::default(), None);
+        assert!(mixer.add_voice(VoiceId(2), constant(0.4, 10), VoiceFades::default(), None));
+        mixer.render(&mut output);
+        mixer.render(&mut output);
+        assert_eq!(output, [0.6]);
+
+        let mut mixer = Mixer::new(1, 200, 2, JVoiceStealPolicy::Quietest);
+        mixer.add_voice(VoiceId(0), constant(0.2, 10), VoiceFades::default(), None);
+        mixer.add_voice(VoiceId(1), constant(0.1, 10), VoiceFades::default(), None);
+        mixer.render(&mut output);
+        assert!(mixer.add_voice(VoiceId(2), constant(0.4, 10), VoiceFades::default(), None));
+        mixer.render(&mut output);
+        mixer.render(&mut output);
+        assert_eq!(output, [0.6]);
+
+        let mut mixer = Mixer::new(1, 48000, 1, JVoiceStealPolicy::Reject);
+        mixer.add_voice(VoiceId(0), constant(0.1, 10), VoiceFades::default(), None);
+        assert!(!mixer.add_voice(VoiceId(1), constant(0.2, 10), VoiceFades::default(), None));
+    }
+
+    #[test]
+    fn master_gain_ramp_test() {
+        // 100 samples ramp
+        let mut mixer = Mixer::new(1, 5000, 1, JVoiceStealPolicy::Oldest);
+        mixer.add_voice(VoiceId(0), constant(1.0, 1000), VoiceFades::default(), None);
+        mixer.set_master_gain(0.0);
+        let mut output = [0.0; 200];
+        mixer.render(&mut output);
+        assert!(output[0] < 1.0 && output[0] > 0.95);
+        assert!((output[49] - 0.5).abs() < 0.02);
+        assert_eq!(output[99], 0.0);
+        assert_eq!(output[199], 0.0);
+    }
+
+    #[test]
+    fn fade_test() {
+        let mut mixer = Mixer::new(1, 200, 1, JVoiceStealPolicy::Oldest);
+        mixer.add_voice(VoiceId(0), constant(1.0, 1000), VoiceFades { fade_in: 4, fade_out: 4 }, None);
+        let mut output = [0.0; 5];
+        mixer.render(&mut output);
+        assert_eq!(output, [0.0, 0.25, 0.5, 0.75, 1.0]);
+
+        // the voice keep playing while it fade out
+        mixer.stop_voice(VoiceId(0));
+        mixer.render(&mut output);
+        assert_eq!(output, [1.0, 0.75, 0.5, 0.25, 0.0]);
+        mixer.render(&mut output);
+        assert!(mixer.is_idle());
+    }
+
+    #[test]
+    fn choke_group_test() {
+        let mut mixer = Mixer::new(1, 200, 4, JVoiceStealPolicy::Oldest);
+        mixer.add_voice(VoiceId(0), constant(0.1, 10), VoiceFades::default(), Some(1));
+        mixer.add_voice(VoiceId(1), constant(0.2, 10), VoiceFades::default(), Some(2));
+        mixer.add_voice(VoiceId(2), constant(0.4, 10), VoiceFades::default(), Some(1));
+        assert!(!mixer.is_voice_active(VoiceId(0)));
+        assert!(mixer.is_voice_active(VoiceId(1)));
+        let mut output = [0.0; 2];
+        mixer.render(&mut output);
+        assert!((output[1] - 0.6).abs() < 1e-6);
+    }
+
+    #[test]
+    fn schedule_test() {
+        let mut mixer = Mixer::new(1, 200, 4, JVoiceStealPolicy::Oldest);
+        mixer.schedule_voice(2, VoiceId(0), constant(0.5, 10), VoiceFades::default(), None);
+        mixer.schedule_voice(5, VoiceId(1), constant(0.25, 10), VoiceFades::default(), None);
+        // the one frame release still plays the frame the stop happens on
+        mixer.schedule_stop_all(6, SequenceRunId(0));
+        assert!(mixer.is_voice_active(VoiceId(1)));
+        assert!(!mixer.is_sounding());
+        let mut output = [0.0; 4];
+        mixer.render(&mut output);
+        assert_eq!(output, [0.0, 0.0, 0.5, 0.5]);
+        assert!(mixer.is_sounding());
+        mixer.render(&mut output);
+        assert_eq!(output, [0.5, 0.75, 0.75, 0.0]);
+        assert!(mixer.is_idle());
+    }
+
+    #[test]
+    fn cancel_sequence_test() {
+        let mut mixer = Mixer::new(1, 200, 4, JVoiceStealPolicy::Oldest);
+        mixer.add_voice(VoiceId(0), constant(0.5, 100), VoiceFades::default(), None);
+        // a sequence run playing voice 1 then stopping everything
+        mixer.schedule_voice(0, VoiceId(1), constant(0.25, 100), VoiceFades::default(), None);
+        mixer.schedule_stop_all(6, SequenceRunId(0));
+        let mut output = [0.0; 4];
+        mixer.render(&mut output);
+        assert_eq!(output, [0.75; 4]);
+
+        // the sequence is stopped early, its stop must not cut the other voice
+        mixer.stop_voice(VoiceId(1));
+        mixer.cancel_sequence_run(SequenceRunId(0));
+        mixer.render(&mut output);
+        mixer.render(&mut output);
+        assert_eq!(output, [0.5; 4]);
+        assert!(mixer.is_voice_active(VoiceId(0)));
+        assert!(!mixer.is_voice_active(VoiceId(1)));
+    }
+
+    #[test]
+    fn voice_events_test() {
+        // 10 frames between progress events
+        let mut mixer = Mixer::new(1, 100, 4, JVoiceStealPolicy::Oldest);
+        mixer.add_voice(VoiceId(0), constant(1.0, 15), VoiceFades::default(), None);
+        mixer.add_voice(VoiceId(1), constant(1.0, 100), VoiceFades::default(), None);
+        mixer.schedule_voice(100, VoiceId(2), constant(1.0, 10), VoiceFades::default(), None);
+        let mut output = [0.0; 12];
+        mixer.render(&mut output);
+        mixer.stop_voice(VoiceId(1));
+        mixer.stop_voice(VoiceId(2));
+        mixer.render(&mut output);
+        let events: Vec<(VoiceId, JVoiceEvent)> = mixer.drain_events().collect();
+        assert_eq!(events, [
+            (VoiceId(0), JVoiceEvent::Started),
+            (VoiceId(1), JVoiceEvent::Started),
+            (VoiceId(0), JVoiceEvent::Progress(12)),
+            (VoiceId(1), JVoiceEvent::Progress(12)),
+            (VoiceId(2), JVoiceEvent::Stopped),
+            (VoiceId(0), JVoiceEvent::Finished),
+            (VoiceId(1), JVoiceEvent::Stopped)
+        ]);
+    }
+
+    /// Output the index of each frame, skipping `step - 1` frames like a sped up sound.
+    struct Counter {
+        position: usize,
+        step: usize
+    }
+
+    impl JFrameSource for Counter {
+
+        fn channels(&self) -> usize {
+            1
+        }
+
+        fn next_frame(&mut self, frame: &mut [f32]) -> bool {
+            frame[0] = self.position as f32;
+            self.position += self.step;
+            true
+        }
+
+        fn seek(&mut self, frame: usize) -> bool {
+            self.position = frame;
+            true
+        }
+
+        fn position(&self) -> Option<usize> {
+            Some(self.position)
+        }
+
+        fn playback_rate(&self) -> f64 {
+            self.step as f64
+        }
+    }
+
+    #[test]
+    fn progress_rate_test() {
+        // 10 frames between progress events, the sound plays twice as fast
+        let mut mixer = Mixer::new(1, 100, 1, JVoiceStealPolicy::Oldest);
+        mixer.add_voice(VoiceId(0), Box::new(Counter { position: 0, step: 2 }), VoiceFades::default(), None);
+        let mut output = [0.0; 6];
+        mixer.render(&mut output);
+        assert_eq!(output, [0.0, 2.0, 4.0, 6.0, 8.0, 10.0]);
+        let events: Vec<(VoiceId, JVoiceEvent)> = mixer.drain_events().collect();
+        assert_eq!(events, [(VoiceId(0), JVoiceEvent::Started), (VoiceId(0), JVoiceEvent::Progress(12))]);
+    }
+
+    #[test]
+    fn pause_seek_test() {
+        // 1 frame transport ramps
+        let mut mixer = Mixer::new(1, 200, 1, JVoiceStealPolicy::Oldest);
+        mixer.add_voice(VoiceId(0), Box::new(Counter { position: 0, step: 1 }), VoiceFades::default(), None);
+        let mut output = [0.0; 3];
+        mixer.render(&mut output);
+        assert_eq!(output, [0.0, 1.0, 2.0]);
+
+        mixer.pause_voice(VoiceId(0));
+        assert!(mixer.is_voice_paused(VoiceId(0)));
+        mixer.render(&mut output);
+        assert_eq!(output, [0.0, 0.0, 0.0]);
+        mixer.resume_voice(VoiceId(0));
+        mixer.render(&mut output);
+        assert_eq!(output, [3.0, 4.0, 5.0]);
+
+        // resumed before the end of its fade out, it still jumps back to where it was paused
+        mixer.pause_voice(VoiceId(0));
+        mixer.resume_voice(VoiceId(0));
+        mixer.render(&mut output);
+        assert_eq!(output, [0.0, 6.0, 7.0]);
+
+        mixer.seek_voice(VoiceId(0), 100, 100);
+        mixer.render(&mut output);
+        assert_eq!(output, [0.0, 100.0, 101.0]);
+    }
+}
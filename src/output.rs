@@ -0,0 +1,71 @@
+use cpal::{Device, FromSample, SampleFormat, SizedSample, StreamConfig, SupportedStreamConfig};
+use cpal::traits::DeviceTrait;
+
+
+/// Longest block given to the stream callbacks, longer device buffers are split in several blocks.
+pub const MAX_BLOCK_FRAMES: usize = 4096;
+
+
+/// Buffer for the longest block of a stream, allocated once so the audio thread never allocates.
+pub fn block_buffer(config: &StreamConfig) -> Vec<f32> {
+    vec![0.0; MAX_BLOCK_FRAMES * config.channels.max(1) as usize]
+}
+
+
+/// Build an output stream for any sample format supported by cpal.
+/// `render` always fill an interleaved f32 buffer, it is converted to the device format at the last moment.
+/// `on_error` is called when the stream fails, a lost device for instance.
+pub fn build_output_stream<R, E>(
+    device: &Device,
+    config: &SupportedStreamConfig,
+    render: R,
+    on_error: E
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    R: FnMut(&mut [f32]) + Send + 'static,
+    E: FnMut(cpal::StreamError) + Send + 'static
+{
+    let stream_config: StreamConfig = config.config();
+    match config.sample_format() {
+        SampleFormat::I8 => build_typed_output_stream::<i8, R, E>(device, &stream_config, render, on_error),
+        SampleFormat::I16 => build_typed_output_stream::<i16, R, E>(device, &stream_config, render, on_error),
+        SampleFormat::I32 => build_typed_output_stream::<i32, R, E>(device, &stream_config, render, on_error),
+        SampleFormat::I64 => build_typed_output_stream::<i64, R, E>(device, &stream_config, render, on_error),
+        SampleFormat::U8 => build_typed_output_stream::<u8, R, E>(device, &stream_config, render, on_error),
+        SampleFormat::U16 => build_typed_output_stream::<u16, R, E>(device, &stream_config, render, on_error),
+        SampleFormat::U32 => build_typed_output_stream::<u32, R, E>(device, &stream_config, render, on_error),
+        SampleFormat::U64 => build_typed_output_stream::<u64, R, E>(device, &stream_config, render, on_error),
+        SampleFormat::F32 => build_typed_output_stream::<f32, R, E>(device, &stream_config, render, on_error),
+        SampleFormat::F64 => build_typed_output_stream::<f64, R, E>(device, &stream_config, render, on_error),
+        _ => Err(cpal::BuildStreamError::StreamConfigNotSupported)
+    }
+}
+
+
+fn build_typed_output_stream<T, R, E>(
+    device: &Device,
+    config: &StreamConfig,
+    mut render: R,
+    on_error: E
+) -> Result<cpal::Stream, cpal::BuildStreamError>
+where
+    T: SizedSample + FromSample<f32>,
+    R: FnMut(&mut [f32]) + Send + 'static,
+    E: FnMut(cpal::StreamError) + Send + 'static
+{
+    let mut mix = block_buffer(config);
+    device.build_output_stream(
+        config,
+        move |output: &mut [T], _info| {
+            for output in output.chunks_mut(mix.len()) {
+                let mix = &mut mix[..output.len()];
+                render(mix);
+                for (out, sample) in output.iter_mut().zip(mix.iter()) {
+                    *out = T::from_sample(sample.clamp(-1.0, 1.0));
+                }
+            }
+        },
+        on_error,
+        None
+    )
+}
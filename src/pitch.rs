@@ -0,0 +1,308 @@
+use std::collections::VecDeque;
+
+use serde::{Serialize, Deserialize};
+
+use crate::smoothed::SmoothedValue;
+use crate::source::JFrameSource;
+
+
+// length of the grains crossfaded by the pitch shifter
+const GRAIN_SECONDS: f32 = 0.04;
+// time taken to switch the pitch shifter in or out
+const BYPASS_RAMP_SECONDS: f32 = 0.01;
+const MIN_RATE: f32 = 0.05;
+const MAX_RATE: f32 = 8.0;
+// past two octaves the grains are too audible to be useful
+const MAX_PITCH_SEMITONES: f32 = 24.0;
+
+
+/// Speed and pitch of a sound.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JPlayback {
+    /// playback speed, it changes the pitch too like a tape played faster.
+    pub rate: f32,
+    /// pitch shift in semitones, it doesn't change the speed.
+    pub pitch: f32
+}
+
+impl JPlayback {
+
+    /// Change the speed of a sound but keep its pitch.
+    pub fn stretched(rate: f32) -> Self {
+        JPlayback {
+            rate,
+            pitch: -12.0 * rate.max(f32::MIN_POSITIVE).log2()
+        }
+    }
+
+    /// The same settings limited to what the pitch shifter can play.
+    pub fn clamped(&self) -> Self {
+        JPlayback {
+            rate: self.rate.clamp(MIN_RATE, MAX_RATE),
+            pitch: self.pitch.clamp(-MAX_PITCH_SEMITONES, MAX_PITCH_SEMITONES)
+        }
+    }
+
+    /// These settings followed by `other`.
+    pub fn then(&self, other: &JPlayback) -> Self {
+        JPlayback {
+            rate: self.rate * other.rate,
+            pitch: self.pitch + other.pitch
+        }
+    }
+
+    fn pitch_ratio(&self) -> f32 {
+        2f32.powf(self.pitch / 12.0)
+    }
+}
+
+impl Default for JPlayback {
+
+    fn default() -> Self {
+        JPlayback {
+            rate: 1.0,
+            pitch: 0.0
+        }
+    }
+}
+
+
+/// Play a source faster or slower, then shift its pitch with two crossfaded delay line taps.
+pub struct PitchShift<S: JFrameSource> {
+    source: S,
+    channels: usize,
+    rate: f64,
+    // position between the second and the third frame of the history
+    position: f64,
+    // 4 frames around the position for the cubic interpolation
+    history: VecDeque<f32>,
+    scratch: Vec<f32>,
+    source_done: bool,
+    // frames of silence pushed after the end of the source
+    tail: usize,
+    ratio: f32,
+    delay_line: Vec<f32>,
+    write: usize,
+    grain_len: usize,
+    // delay of the first tap relative to the grain length, the second is half a grain away
+    phase: f32,
+    wet: SmoothedValue
+}
+
+impl<S: JFrameSource> PitchShift<S> {
+
+    pub fn new(source: S, sample_rate: u32, playback: JPlayback) -> Self {
+        let channels = source.channels();
+        let grain_len = ((sample_rate as f32 * GRAIN_SECONDS) as usize).max(4);
+        let mut shift = PitchShift {
+            source,
+            channels,
+            rate: 1.0,
+            position: 0.0,
+            history: VecDeque::with_capacity(4 * channels),
+            scratch: vec![0.0; channels],
+            source_done: false,
+            tail: 0,
+            ratio: 1.0,
+            delay_line: vec![0.0; (grain_len + 2) * channels],
+            write: 0,
+            grain_len,
+            phase: 0.0,
+            wet: SmoothedValue::new(0.0, (sample_rate as f32 * BYPASS_RAMP_SECONDS) as usize)
+        };
+        shift.set_playback(playback);
+        shift.wet.set_immediate(if shift.ratio == 1.0 { 0.0 } else { 1.0 });
+        shift.reset();
+        shift
+    }
+
+    fn reset(&mut self) {
+        self.position = 0.0;
+        self.source_done = false;
+        self.tail = 0;
+        self.history.clear();
+        self.history.resize(self.channels, 0.0);
+        for _ in 0..3 {
+            self.push_frame();
+        }
+        self.delay_line.fill(0.0);
+        self.phase = 0.0;
+    }
+
+    fn push_frame(&mut self) {
+        if !self.source_done && !self.source.next_frame(&mut self.scratch) {
+            self.source_done = true;
+        }
+        if self.source_done {
+            self.scratch.fill(0.0);
+            self.tail += 1;
+        }
+        self.history.extend(self.scratch.iter());
+    }
+
+    /// Sample of the delay line `delay` frames in the past.
+    #[inline]
+    fn tap(&self, channel: usize, delay: f32) -> f32 {
+        let len = self.delay_line.len() / self.channels;
+        let position = (self.write + len) as f32 - delay;
+        let index = position.floor() as usize;
+        let frac = position - position.floor();
+        let a = self.delay_line[(index % len) * self.channels + channel];
+        let b = self.delay_line[((index + 1) % len) * self.channels + channel];
+        a + (b - a) * frac
+    }
+}
+
+/// Catmull-Rom interpolation between `p1` and `p2`.
+#[inline]
+fn hermite(p0: f32, p1: f32, p2: f32, p3: f32, t: f32) -> f32 {
+    let c1 = 0.5 * (p2 - p0);
+    let c2 = p0 - 2.5 * p1 + 2.0 * p2 - 0.5 * p3;
+    let c3 = 0.5 * (p3 - p0) + 1.5 * (p1 - p2);
+    ((c3 * t + c2) * t + c1) * t + p1
+}
+
+impl<S: JFrameSource> JFrameSource for PitchShift<S> {
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn next_frame(&mut self, frame: &mut [f32]) -> bool {
+        // the frame at the position is past the end of the source
+        if self.tail >= 3 {
+            return false;
+        }
+
+        let t = self.position as f32;
+        for (c, sample) in frame.iter_mut().enumerate() {
+            let h = |i: usize| self.history[i * self.channels + c];
+            *sample = hermite(h(0), h(1), h(2), h(3), t);
+        }
+        self.position += self.rate;
+        while self.position >= 1.0 {
+            self.position -= 1.0;
+            self.history.drain(..self.channels);
+            self.push_frame();
+        }
+
+        let offset = self.write * self.channels;
+        self.delay_line[offset..offset + self.channels].copy_from_slice(frame);
+        let wet = self.wet.next_value();
+        if wet > 0.0 {
+            let phases = [self.phase, (self.phase + 0.5) % 1.0];
+            for (c, sample) in frame.iter_mut().enumerate() {
+                let shifted: f32 = phases
+                    .iter()
+                    .map(|p| {
+                        // hann windows half a grain apart sum to one
+                        let weight = (std::f32::consts::PI * p).sin().powi(2);
+                        self.tap(c, p * self.grain_len as f32) * weight
+                    })
+                    .sum();
+                *sample = *sample * (1.0 - wet) + shifted * wet;
+            }
+        }
+        // the delay shrinks when the pitch goes up
+        self.phase = (self.phase + (1.0 - self.ratio) / self.grain_len as f32).rem_euclid(1.0);
+        self.write = (self.write + 1) % (self.delay_line.len() / self.channels);
+        true
+    }
+
+    fn seek(&mut self, frame: usize) -> bool {
+        if !self.source.seek(frame) {
+            return false;
+        }
+        self.reset();
+        true
+    }
+
+    fn position(&self) -> Option<usize> {
+        // the history holds a frame before and two frames after the interpolated position
+        let position = (self.source.position()? + self.tail) as f64 - 3.0 + self.position;
+        Some(position.round().max(0.0) as usize)
+    }
+
+    fn set_playback(&mut self, playback: JPlayback) -> bool {
+        let playback = playback.clamped();
+        self.rate = playback.rate as f64;
+        self.ratio = playback.pitch_ratio();
+        self.wet.set_target(if self.ratio == 1.0 { 0.0 } else { 1.0 });
+        true
+    }
+
+    fn playback_rate(&self) -> f64 {
+        self.rate
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{JPlayback, PitchShift};
+    use crate::source::JFrameSource;
+
+    struct Sine {
+        frequency: f32,
+        sample_rate: u32,
+        position: usize,
+        len: usize
+    }
+
+    impl JFrameSource for Sine {
+
+        fn channels(&self) -> usize {
+            1
+        }
+
+        fn next_frame(&mut self, frame: &mut [f32]) -> bool {
+            if self.position == self.len {
+                return false;
+            }
+            let t = self.position as f32 / self.sample_rate as f32;
+            frame[0] = (2.0 * std::f32::consts::PI * self.frequency * t).sin();
+            self.position += 1;
+            true
+        }
+    }
+
+    fn render(playback: JPlayback) -> Vec<f32> {
+        let sine = Sine { frequency: 100.0, sample_rate: 8000, position: 0, len: 8000 };
+        let mut shift = PitchShift::new(sine, 8000, playback);
+        let mut frame = [0.0];
+        let mut output = Vec::new();
+        while shift.next_frame(&mut frame) {
+            output.push(frame[0]);
+        }
+        output
+    }
+
+    fn zero_crossings(data: &[f32]) -> usize {
+        // skip the delay of the pitch shifter
+        data[400..].windows(2).filter(|w| (w[0] < 0.0) != (w[1] < 0.0)).count()
+    }
+
+    #[test]
+    fn rate_pitch_test() {
+        let unchanged = render(JPlayback::default());
+        assert_eq!(unchanged.len(), 8000);
+        assert!((unchanged[20] - (2.0 * std::f32::consts::PI * 100.0 * 20.0 / 8000.0).sin()).abs() < 1e-6);
+
+        let fast = render(JPlayback { rate: 2.0, pitch: 0.0 });
+        assert_eq!(fast.len(), 4000);
+        assert_eq!(JPlayback { rate: 20.0, pitch: -30.0 }.clamped(), JPlayback { rate: 8.0, pitch: -24.0 });
+
+        // an octave up keeps the length and doubles the frequency
+        let shifted = render(JPlayback { rate: 1.0, pitch: 12.0 });
+        assert_eq!(shifted.len(), 8000);
+        let ratio = zero_crossings(&shifted) as f32 / zero_crossings(&unchanged) as f32;
+        assert!((ratio - 2.0).abs() < 0.1, "{ratio}");
+
+        // a stretched sound is longer but keeps its frequency
+        let stretched = render(JPlayback::stretched(0.5));
+        assert_eq!(stretched.len(), 16000);
+        let ratio = zero_crossings(&stretched) as f32 / zero_crossings(&unchanged) as f32;
+        assert!((ratio - 2.0).abs() < 0.1, "{ratio}");
+    }
+}
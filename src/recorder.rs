@@ -0,0 +1,204 @@
+use std::sync::mpsc::Sender;
+
+use cpal::Device;
+use cpal::traits::{DeviceTrait, StreamTrait};
+use rtrb::{Consumer, RingBuffer};
+use serde::{Serialize, Deserialize};
+
+use crate::app::JAppEvent;
+use crate::bridge::send_block;
+use crate::decoder::JSoundErrors;
+use crate::gain::db_to_gain;
+use crate::input::build_input_stream;
+
+
+// captured audio waiting for the app thread, it is emptied on every event loop
+const CAPTURE_BUFFER_SECONDS: f32 = 2.0;
+// kept around the trimmed sound so its attack and tail aren't cut
+const TRIM_PADDING_SECONDS: f32 = 0.01;
+
+
+#[derive(Debug)]
+pub enum JRecordErrors {
+    NoDevice,
+    AlreadyRecording,
+    NotRecording,
+    Stream(String),
+    /// nothing but silence was recorded.
+    Empty,
+    Io(std::io::Error),
+    /// the saved recording couldn't be loaded back.
+    Sound(JSoundErrors)
+}
+
+impl std::fmt::Display for JRecordErrors {
+
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JRecordErrors::NoDevice => write!(f, "No input device to record from"),
+            JRecordErrors::AlreadyRecording => write!(f, "A recording is already running"),
+            JRecordErrors::NotRecording => write!(f, "Nothing is being recorded"),
+            JRecordErrors::Stream(reason) => write!(f, "Couldn't record from the input device: {reason}"),
+            JRecordErrors::Empty => write!(f, "The recording is silent"),
+            JRecordErrors::Io(e) => write!(f, "Couldn't save the recording: {e}"),
+            JRecordErrors::Sound(e) => write!(f, "Couldn't load the recording: {e}")
+        }
+    }
+}
+
+impl From<std::io::Error> for JRecordErrors {
+
+    fn from(value: std::io::Error) -> Self {
+        JRecordErrors::Io(value)
+    }
+}
+
+
+/// How new sounds are recorded.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JRecording {
+    /// input device, the default one when none.
+    pub device: Option<String>,
+    /// remove the silence before and after the sound.
+    pub trim_silence: bool,
+    /// level under which the recording is silent, in dB.
+    pub silence_db: f32,
+    /// folder where the recordings are saved as wav files.
+    pub directory: String
+}
+
+impl Default for JRecording {
+
+    fn default() -> Self {
+        JRecording {
+            device: None,
+            trim_silence: true,
+            silence_db: -50.0,
+            directory: String::from(concat!(env!("CARGO_MANIFEST_DIR"), "/resources/recordings"))
+        }
+    }
+}
+
+
+/// Capture of an input device, kept in memory until it is stopped.
+pub struct Recorder {
+    // dropping the stream stop it.
+    _stream: cpal::Stream,
+    consumer: Consumer<f32>,
+    channels: u16,
+    sample_rate: u32,
+    data: Vec<f32>
+}
+
+impl Recorder {
+
+    /// Start capturing `device`, a failure of the capture is sent to `errors`.
+    pub fn start(device: &Device, errors: Sender<JAppEvent>) -> Result<Self, JRecordErrors> {
+        let config = device
+            .default_input_config()
+            .map_err(|e| JRecordErrors::Stream(e.to_string()))?;
+        let channels = config.channels();
+        let sample_rate = config.sample_rate().0;
+        let capacity = (sample_rate as f32 * CAPTURE_BUFFER_SECONDS) as usize * channels as usize;
+        let (mut producer, consumer) = RingBuffer::new(capacity.max(1));
+        let stream = build_input_stream(
+            device,
+            &config,
+            move |input: &[f32]| send_block(&mut producer, input),
+            move |err| {
+                let _ = errors.send(JAppEvent::RecordingError(err.to_string()));
+            }
+        ).map_err(|e| JRecordErrors::Stream(e.to_string()))?;
+        stream.play().map_err(|e| JRecordErrors::Stream(e.to_string()))?;
+        Ok(Recorder {
+            _stream: stream,
+            consumer,
+            channels,
+            sample_rate,
+            data: Vec::new()
+        })
+    }
+
+    /// Move the captured audio out of the capture buffer before it fills up.
+    pub fn drain(&mut self) {
+        if let Ok(chunk) = self.consumer.read_chunk(self.consumer.slots()) {
+            let (first, second) = chunk.as_slices();
+            self.data.extend_from_slice(first);
+            self.data.extend_from_slice(second);
+            chunk.commit_all();
+        }
+    }
+
+    /// Stop the capture, the samples are trimmed when `trim_below_db` is given.
+    pub fn stop(mut self, trim_below_db: Option<f32>) -> RecordedSound {
+        self.drain();
+        let mut data = self.data;
+        if let Some(silence_db) = trim_below_db {
+            let padding = (self.sample_rate as f32 * TRIM_PADDING_SECONDS) as usize;
+            let range = trim_silence(&data, self.channels as usize, silence_db, padding);
+            data.truncate(range.end);
+            data.drain(..range.start);
+        }
+        RecordedSound {
+            data,
+            channels: self.channels,
+            sample_rate: self.sample_rate
+        }
+    }
+}
+
+
+pub struct RecordedSound {
+    pub data: Vec<f32>,
+    pub channels: u16,
+    pub sample_rate: u32
+}
+
+impl RecordedSound {
+
+    /// Write the sound as a 32 bits float wav file.
+    pub fn save_wav(&self, path: &std::path::Path) -> std::io::Result<()> {
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+        let header = wav::Header::new(wav::header::WAV_FORMAT_IEEE_FLOAT, self.channels, self.sample_rate, 32);
+        let mut file = std::fs::File::create(path)?;
+        wav::write(header, &wav::BitDepth::ThirtyTwoFloat(self.data.clone()), &mut file)
+    }
+}
+
+
+/// Samples between the first and the last frame louder than `silence_db`, widened by `padding` frames.
+fn trim_silence(data: &[f32], channels: usize, silence_db: f32, padding: usize) -> std::ops::Range<usize> {
+    let threshold = db_to_gain(silence_db);
+    let channels = channels.max(1);
+    let loud = |frame: &[f32]| frame.iter().any(|s| s.abs() > threshold);
+    let frames: Vec<&[f32]> = data.chunks_exact(channels).collect();
+    let first = match frames.iter().position(|f| loud(f)) {
+        Some(first) => first,
+        None => return 0..0
+    };
+    let last = frames.iter().rposition(|f| loud(f)).unwrap_or(first);
+    let start = first.saturating_sub(padding);
+    let end = (last + 1 + padding).min(frames.len());
+    start * channels..end * channels
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::trim_silence;
+
+    #[test]
+    fn trim_silence_test() {
+        let mut data = vec![0.0001; 20];
+        data[8] = 0.5;
+        data[13] = -0.2;
+        // stereo, the loud frames are 4 and 6
+        assert_eq!(trim_silence(&data, 2, -50.0, 1), 6..16);
+        assert_eq!(trim_silence(&data, 2, -50.0, 10), 0..20);
+        assert_eq!(trim_silence(&data, 1, -50.0, 0), 8..14);
+        assert_eq!(trim_silence(&data, 1, 0.0, 0), 0..0);
+    }
+}
@@ -0,0 +1,278 @@
+use std::collections::VecDeque;
+
+use serde::{Serialize, Deserialize};
+
+use crate::pitch::JPlayback;
+use crate::source::JFrameSource;
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum JResampleQuality {
+    /// linear interpolation between two frames.
+    Fast,
+    /// blackman windowed sinc.
+    #[default]
+    HighQuality
+}
+
+
+// number of zero crossing on each side of the sinc kernel
+const SINC_ZERO_CROSSINGS: usize = 16;
+// number of kernel values precomputed between two zero crossings
+const SINC_TABLE_RESOLUTION: usize = 256;
+
+
+enum Kernel {
+    Linear,
+    Sinc {
+        table: Vec<f32>,
+        // table entries per input frame
+        resolution: f64
+    }
+}
+
+impl Kernel {
+
+    fn sinc(half_width: usize, cutoff: f64) -> Kernel {
+        let resolution = SINC_TABLE_RESOLUTION as f64 * cutoff;
+        let len = (half_width as f64 * resolution).ceil() as usize + 2;
+        let table = (0..len)
+            .map(|i| {
+                let t = i as f64 / resolution;
+                if t >= half_width as f64 {
+                    return 0.0;
+                }
+                let x = std::f64::consts::PI * cutoff * t;
+                let sinc = if x == 0.0 { 1.0 } else { x.sin() / x };
+                // blackman window over [-half_width, half_width]
+                let w = std::f64::consts::PI * (t / half_width as f64 + 1.0);
+                let window = 0.42 - 0.5 * w.cos() + 0.08 * (2.0 * w).cos();
+                (cutoff * sinc * window) as f32
+            })
+            .collect();
+        Kernel::Sinc { table, resolution }
+    }
+
+    #[inline]
+    fn value(&self, t: f64) -> f32 {
+        match self {
+            Kernel::Linear => (1.0 - t.abs()).max(0.0) as f32,
+            Kernel::Sinc { table, resolution } => {
+                let pos = t.abs() * resolution;
+                let idx = pos as usize;
+                if idx + 1 >= table.len() {
+                    return 0.0;
+                }
+                let frac = (pos - idx as f64) as f32;
+                table[idx] + (table[idx + 1] - table[idx]) * frac
+            }
+        }
+    }
+}
+
+
+/// Convert a source from one sample rate to another.
+pub struct Resampler<S: JFrameSource> {
+    source: S,
+    channels: usize,
+    kernel: Kernel,
+    // number of input frames on each side of the interpolated position
+    half_width: usize,
+    // input frames consumed per output frame
+    step: f64,
+    frac: f64,
+    window: VecDeque<f32>,
+    scratch: Vec<f32>,
+    source_done: bool,
+    // frames of silence pushed after the end of the source
+    tail: usize
+}
+
+impl<S: JFrameSource> Resampler<S> {
+
+    pub fn new(source: S, from_rate: u32, to_rate: u32, quality: JResampleQuality) -> Self {
+        let step = from_rate as f64 / to_rate as f64;
+        let (kernel, half_width) = match quality {
+            JResampleQuality::Fast => (Kernel::Linear, 1),
+            JResampleQuality::HighQuality => {
+                // lower the cutoff when downsampling to avoid aliasing
+                let cutoff = (1.0 / step).min(1.0);
+                let half_width = (SINC_ZERO_CROSSINGS as f64 / cutoff).ceil() as usize;
+                (Kernel::sinc(half_width, cutoff), half_width)
+            }
+        };
+
+        let channels = source.channels();
+        let mut resampler = Resampler {
+            source,
+            channels,
+            kernel,
+            half_width,
+            step,
+            frac: 0.0,
+            window: VecDeque::with_capacity(2 * half_width * channels),
+            scratch: vec![0.0; channels],
+            source_done: false,
+            tail: 0
+        };
+        resampler.reset();
+        resampler
+    }
+
+    fn reset(&mut self) {
+        self.frac = 0.0;
+        self.source_done = false;
+        self.tail = 0;
+        // center the first frame of the source in the window
+        self.window.clear();
+        self.window.resize((self.half_width - 1) * self.channels, 0.0);
+        for _ in 0..self.half_width + 1 {
+            self.push_frame();
+        }
+    }
+
+    fn push_frame(&mut self) {
+        if !self.source_done && !self.source.next_frame(&mut self.scratch) {
+            self.source_done = true;
+        }
+        if self.source_done {
+            self.scratch.fill(0.0);
+            self.tail += 1;
+        }
+        self.window.extend(self.scratch.iter());
+    }
+}
+
+impl<S: JFrameSource> JFrameSource for Resampler<S> {
+
+    fn channels(&self) -> usize {
+        self.channels
+    }
+
+    fn next_frame(&mut self, frame: &mut [f32]) -> bool {
+        // the last real frame went past the center of the window
+        if self.tail > self.half_width {
+            return false;
+        }
+
+        frame.fill(0.0);
+        let center = self.half_width as f64 - 1.0 + self.frac;
+        for i in 0..2 * self.half_width {
+            let weight = self.kernel.value(i as f64 - center);
+            if weight == 0.0 {
+                continue;
+            }
+            let offset = i * self.channels;
+            for (c, sample) in frame.iter_mut().enumerate() {
+                *sample += self.window[offset + c] * weight;
+            }
+        }
+
+        self.frac += self.step;
+        while self.frac >= 1.0 {
+            self.frac -= 1.0;
+            self.window.drain(..self.channels);
+            self.push_frame();
+        }
+        true
+    }
+
+    fn seek(&mut self, frame: usize) -> bool {
+        if !self.source.seek(frame) {
+            return false;
+        }
+        self.reset();
+        true
+    }
+
+    fn position(&self) -> Option<usize> {
+        // the window reads ahead of the interpolated position
+        let lookahead = (self.half_width + 1) as f64 - self.frac - self.tail as f64;
+        let position = self.source.position()? as f64 - lookahead * self.source.playback_rate();
+        Some(position.round().max(0.0) as usize)
+    }
+
+    fn set_playback(&mut self, playback: JPlayback) -> bool {
+        self.source.set_playback(playback)
+    }
+
+    fn playback_rate(&self) -> f64 {
+        self.source.playback_rate()
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{JResampleQuality, Resampler};
+    use crate::source::JFrameSource;
+
+    struct Ramp {
+        current: usize,
+        len: usize
+    }
+
+    impl JFrameSource for Ramp {
+
+        fn channels(&self) -> usize {
+            1
+        }
+
+        fn next_frame(&mut self, frame: &mut [f32]) -> bool {
+            if self.current == self.len {
+                return false;
+            }
+            frame[0] = self.current as f32;
+            self.current += 1;
+            true
+        }
+
+        fn position(&self) -> Option<usize> {
+            Some(self.current)
+        }
+    }
+
+    fn collect<S: JFrameSource>(mut source: S) -> Vec<f32> {
+        let mut out = Vec::new();
+        let mut frame = [0.0];
+        while source.next_frame(&mut frame) {
+            out.push(frame[0]);
+        }
+        out
+    }
+
+    #[test]
+    fn linear_resample_test() {
+        let out = collect(Resampler::new(Ramp { current: 0, len: 4 }, 24000, 48000, JResampleQuality::Fast));
+        assert_eq!(out, vec![0.0, 0.5, 1.0, 1.5, 2.0, 2.5, 3.0, 1.5]);
+
+        let out = collect(Resampler::new(Ramp { current: 0, len: 8 }, 48000, 24000, JResampleQuality::Fast));
+        assert_eq!(out, vec![0.0, 2.0, 4.0, 6.0]);
+    }
+
+    #[test]
+    fn position_test() {
+        // the position is the frame of the source the next output frame is interpolated at
+        let mut resampler = Resampler::new(Ramp { current: 0, len: 100 }, 48000, 24000, JResampleQuality::Fast);
+        assert_eq!(resampler.position(), Some(0));
+        let mut frame = [0.0];
+        resampler.next_frame(&mut frame);
+        resampler.next_frame(&mut frame);
+        assert_eq!(resampler.position(), Some(4));
+
+        let mut resampler = Resampler::new(Ramp { current: 0, len: 1000 }, 44100, 48000, JResampleQuality::HighQuality);
+        for _ in 0..480 {
+            resampler.next_frame(&mut frame);
+        }
+        assert_eq!(resampler.position(), Some(441));
+    }
+
+    #[test]
+    fn sinc_resample_length_test() {
+        let out = collect(Resampler::new(Ramp { current: 0, len: 441 }, 44100, 48000, JResampleQuality::HighQuality));
+        assert!((479..=481).contains(&out.len()));
+        // away from the edges a ramp should stay a ramp
+        let expected = 240.0 * 44100.0 / 48000.0;
+        assert!((out[240] - expected).abs() < 0.05);
+    }
+}
@@ -0,0 +1,88 @@
+use crate::app::{JMouseButton, SoundId};
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SequenceId(pub(crate) usize);
+
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JSequenceStep {
+    /// play a sound with a gain in dB added to its own.
+    Play(SoundId, f32),
+    /// wait before the next step, in milliseconds.
+    Wait(f32),
+    /// click a mouse button.
+    Input(JMouseButton),
+    /// stop every sound playing.
+    StopAll
+}
+
+
+/// Steps played one after the other, only waits take time.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct JSequence {
+    pub steps: Vec<JSequenceStep>
+}
+
+impl JSequence {
+
+    pub fn new() -> Self {
+        JSequence::default()
+    }
+
+    pub fn play(mut self, sound_id: SoundId, gain_db: f32) -> Self {
+        self.steps.push(JSequenceStep::Play(sound_id, gain_db));
+        self
+    }
+
+    pub fn wait(mut self, ms: f32) -> Self {
+        self.steps.push(JSequenceStep::Wait(ms));
+        self
+    }
+
+    pub fn input(mut self, button: JMouseButton) -> Self {
+        self.steps.push(JSequenceStep::Input(button));
+        self
+    }
+
+    pub fn stop_all(mut self) -> Self {
+        self.steps.push(JSequenceStep::StopAll);
+        self
+    }
+
+    /// Each step with its time from the start of the sequence in milliseconds.
+    pub fn timeline(&self) -> Vec<(f32, JSequenceStep)> {
+        let mut time = 0.0;
+        let mut timeline = Vec::with_capacity(self.steps.len());
+        for step in self.steps.iter() {
+            match step {
+                JSequenceStep::Wait(ms) => time += ms.max(0.0),
+                step => timeline.push((time, *step))
+            }
+        }
+        timeline
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{JSequence, JSequenceStep};
+    use crate::app::SoundId;
+
+    #[test]
+    fn timeline_test() {
+        let sequence = JSequence::new()
+            .play(SoundId(0), 0.0)
+            .wait(500.0)
+            .play(SoundId(1), -6.0)
+            .wait(250.0)
+            .wait(-10.0)
+            .stop_all();
+        assert_eq!(sequence.timeline(), [
+            (0.0, JSequenceStep::Play(SoundId(0), 0.0)),
+            (500.0, JSequenceStep::Play(SoundId(1), -6.0)),
+            (750.0, JSequenceStep::StopAll)
+        ]);
+    }
+}
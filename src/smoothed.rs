@@ -0,0 +1,49 @@
+/// A value ramping linearly toward its target to avoid clicks when a gain changes.
+pub struct SmoothedValue {
+    current: f32,
+    target: f32,
+    step: f32,
+    remaining: usize,
+    ramp_len: usize
+}
+
+impl SmoothedValue {
+
+    pub fn new(value: f32, ramp_len: usize) -> Self {
+        SmoothedValue {
+            current: value,
+            target: value,
+            step: 0.0,
+            remaining: 0,
+            ramp_len: ramp_len.max(1)
+        }
+    }
+
+    pub fn set_target(&mut self, target: f32) {
+        if target == self.target {
+            return;
+        }
+        self.target = target;
+        self.remaining = self.ramp_len;
+        self.step = (target - self.current) / self.ramp_len as f32;
+    }
+
+    pub fn set_immediate(&mut self, value: f32) {
+        self.current = value;
+        self.target = value;
+        self.remaining = 0;
+    }
+
+    pub fn value(&self) -> f32 {
+        self.current
+    }
+
+    #[inline]
+    pub fn next_value(&mut self) -> f32 {
+        if self.remaining > 0 {
+            self.remaining -= 1;
+            self.current = if self.remaining == 0 { self.target } else { self.current + self.step };
+        }
+        self.current
+    }
+}
@@ -0,0 +1,257 @@
+use serde::{Serialize, Deserialize};
+
+use crate::effects::JEffect;
+use crate::gain::{db_to_gain, gain_to_db};
+use crate::loudness::JLoudness;
+use crate::pitch::JPlayback;
+
+
+const DEFAULT_LOOP_CROSSFADE_MS: f32 = 10.0;
+// the normalization never changes a sound by more than this
+const MAX_NORMALIZE_DB: f32 = 24.0;
+// sounds quieter than this are left alone, the R128 absolute gate
+const NORMALIZE_FLOOR_DB: f32 = -70.0;
+
+
+/// Level a sound is brought to before its own gain is applied.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum JNormalize {
+    /// target peak in dBFS, the true peak once the sound is analyzed.
+    Peak(f32),
+    /// target loudness in LUFS.
+    Loudness(f32)
+}
+
+
+/// Playback settings of a sound in the library, saved in the app state.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JSoundEntry {
+    pub path: String,
+    pub gain_db: f32,
+    /// -1.0 is full left, 1.0 full right.
+    pub pan: f32,
+    pub normalize: Option<JNormalize>,
+    /// override the app fade in.
+    pub fade_in_ms: Option<f32>,
+    /// override the app fade out.
+    pub fade_out_ms: Option<f32>,
+    pub playback: JPlayback,
+    /// inserted after the gain and pan.
+    pub effects: Vec<JEffect>,
+    pub loop_points: Option<JLoopPoints>,
+    /// playing the sound cuts the other sounds of its group.
+    pub choke_group: Option<u32>,
+    pub loudness_cache: Option<JLoudnessCache>
+}
+
+impl JSoundEntry {
+
+    pub fn new(path: &str) -> Self {
+        JSoundEntry {
+            path: String::from(path),
+            ..Default::default()
+        }
+    }
+}
+
+impl Default for JSoundEntry {
+
+    fn default() -> Self {
+        JSoundEntry {
+            path: String::new(),
+            gain_db: 0.0,
+            pan: 0.0,
+            normalize: None,
+            fade_in_ms: None,
+            fade_out_ms: None,
+            playback: JPlayback::default(),
+            effects: Vec::new(),
+            loop_points: None,
+            choke_group: None,
+            loudness_cache: None
+        }
+    }
+}
+
+
+/// Section of a sound played again and again, positions are in frames.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JLoopPoints {
+    pub start: u64,
+    /// the end of the sound when none.
+    pub end: Option<u64>,
+    /// times the section is played before going on to the end, loop until stopped when none.
+    pub count: Option<u32>,
+    /// crossfade between the end and the start of the section.
+    pub crossfade_ms: f32
+}
+
+impl Default for JLoopPoints {
+
+    fn default() -> Self {
+        JLoopPoints {
+            start: 0,
+            end: None,
+            count: None,
+            crossfade_ms: DEFAULT_LOOP_CROSSFADE_MS
+        }
+    }
+}
+
+
+/// Loop points checked against the sound length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoopRegion {
+    pub start: usize,
+    pub end: usize,
+    /// the last `crossfade` frames of the section fade into its first ones.
+    pub crossfade: usize
+}
+
+impl LoopRegion {
+
+    pub fn new(points: &JLoopPoints, frames: usize, sample_rate: u32) -> Option<Self> {
+        let end = points.end.map(|e| (e as usize).min(frames)).unwrap_or(frames);
+        let start = points.start as usize;
+        if start >= end {
+            return None;
+        }
+        let crossfade = (points.crossfade_ms.max(0.0) * sample_rate as f32 / 1000.0) as usize;
+        Some(LoopRegion {
+            start,
+            end,
+            crossfade: crossfade.min((end - start) / 2)
+        })
+    }
+
+    pub fn whole(frames: usize) -> Option<Self> {
+        if frames == 0 {
+            return None;
+        }
+        Some(LoopRegion { start: 0, end: frames, crossfade: 0 })
+    }
+}
+
+
+/// Loudness analysis of a sound file, only valid while the file is unchanged.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct JLoudnessCache {
+    pub file_size: u64,
+    /// modification time in seconds since the unix epoch.
+    pub modified: u64,
+    pub loudness: JLoudness
+}
+
+impl JLoudnessCache {
+
+    pub fn new(path: &str, loudness: JLoudness) -> Option<Self> {
+        let (file_size, modified) = file_fingerprint(path)?;
+        Some(JLoudnessCache { file_size, modified, loudness })
+    }
+
+    pub fn is_valid_for(&self, path: &str) -> bool {
+        file_fingerprint(path) == Some((self.file_size, self.modified))
+    }
+}
+
+
+fn file_fingerprint(path: &str) -> Option<(u64, u64)> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_secs();
+    Some((metadata.len(), modified))
+}
+
+
+/// Levels measured once when a sound is loaded.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SoundLevels {
+    /// highest absolute sample value.
+    pub peak: f32,
+    /// average power of the sound in dBFS until the R128 integrated loudness is known.
+    pub loudness: f32
+}
+
+impl SoundLevels {
+
+    pub fn measure(data: &[f32]) -> Self {
+        let peak = data.iter().fold(0.0f32, |p, s| p.max(s.abs()));
+        let power = data.iter().map(|s| (*s as f64) * (*s as f64)).sum::<f64>() / data.len().max(1) as f64;
+        SoundLevels {
+            peak,
+            loudness: 10.0 * power.max(1e-20).log10() as f32
+        }
+    }
+
+    pub fn set_loudness(&mut self, loudness: &JLoudness) {
+        self.peak = db_to_gain(loudness.true_peak);
+        self.loudness = loudness.integrated;
+    }
+
+    /// Gain bringing the sound to the normalization target, limited to +/-24 dB.
+    /// Silent sounds are not normalized, they would be boosted to full scale noise.
+    pub fn normalize_gain(&self, normalize: &JNormalize) -> f32 {
+        let (target, level) = match normalize {
+            JNormalize::Peak(target) => (*target, gain_to_db(self.peak)),
+            JNormalize::Loudness(target) => (*target, self.loudness)
+        };
+        if level.is_nan() || level <= NORMALIZE_FLOOR_DB {
+            return 1.0;
+        }
+        db_to_gain((target - level).clamp(-MAX_NORMALIZE_DB, MAX_NORMALIZE_DB))
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{JLoopPoints, JNormalize, LoopRegion, SoundLevels};
+
+    #[test]
+    fn normalize_gain_test() {
+        let levels = SoundLevels::measure(&[0.5, -0.25, 0.0, 0.1]);
+        assert_eq!(levels.peak, 0.5);
+        let gain = levels.normalize_gain(&JNormalize::Peak(0.0));
+        assert!((gain - 2.0).abs() < 1e-4);
+
+        // a full scale square wave has the same power as the reference
+        let levels = SoundLevels::measure(&[1.0, -1.0, 1.0, -1.0]);
+        assert!(levels.loudness.abs() < 1e-4);
+        let gain = levels.normalize_gain(&JNormalize::Loudness(-20.0));
+        assert!((gain - 0.1).abs() < 1e-4);
+
+        // the correction is limited to 24 dB
+        let levels = SoundLevels::measure(&[0.001, -0.001]);
+        let gain = levels.normalize_gain(&JNormalize::Peak(0.0));
+        assert!((gain - 15.8489).abs() < 1e-3);
+    }
+
+    #[test]
+    fn normalize_silence_test() {
+        let levels = SoundLevels::measure(&[0.0; 16]);
+        assert_eq!(levels.normalize_gain(&JNormalize::Peak(0.0)), 1.0);
+        assert_eq!(levels.normalize_gain(&JNormalize::Loudness(-14.0)), 1.0);
+        let levels = SoundLevels::measure(&[]);
+        assert_eq!(levels.normalize_gain(&JNormalize::Loudness(-14.0)), 1.0);
+    }
+
+    #[test]
+    fn loop_region_test() {
+        let points = JLoopPoints { start: 100, end: Some(5000), count: None, crossfade_ms: 10.0 };
+        let region = LoopRegion::new(&points, 1000, 1000).unwrap();
+        assert_eq!(region, LoopRegion { start: 100, end: 1000, crossfade: 10 });
+
+        // the crossfade can't be longer than half of the section
+        let points = JLoopPoints { start: 990, crossfade_ms: 100.0, ..Default::default() };
+        assert_eq!(LoopRegion::new(&points, 1000, 1000).unwrap().crossfade, 5);
+
+        let points = JLoopPoints { start: 1000, ..Default::default() };
+        assert_eq!(LoopRegion::new(&points, 1000, 1000), None);
+    }
+}
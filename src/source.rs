@@ -0,0 +1,70 @@
+use crate::effects::EffectChain;
+use crate::pitch::JPlayback;
+
+
+/// Something the audio callback can pull interleaved frames from.
+pub trait JFrameSource: Send {
+
+    fn channels(&self) -> usize;
+
+    /// Write the next frame in `frame`, return false once the source is exhausted.
+    fn next_frame(&mut self, frame: &mut [f32]) -> bool;
+
+    /// Continue from a frame of the sound at the root of the source, return false if it can't seek.
+    fn seek(&mut self, _frame: usize) -> bool {
+        false
+    }
+
+    /// Frame of the sound at the root of the source the next pulled frame comes from, none if unknown.
+    fn position(&self) -> Option<usize> {
+        None
+    }
+
+    /// Change the speed and pitch of the sound, return false if the source can't.
+    fn set_playback(&mut self, _playback: JPlayback) -> bool {
+        false
+    }
+
+    /// Frames of the sound at the root of the source played for each frame pulled.
+    fn playback_rate(&self) -> f64 {
+        1.0
+    }
+
+    /// Take a new chain for the sound own effects, return the chain to drop outside the audio thread,
+    /// `chain` itself when the source has no effects.
+    fn set_effects(&mut self, chain: EffectChain) -> EffectChain {
+        chain
+    }
+}
+
+
+impl<S: JFrameSource + ?Sized> JFrameSource for Box<S> {
+
+    fn channels(&self) -> usize {
+        (**self).channels()
+    }
+
+    fn next_frame(&mut self, frame: &mut [f32]) -> bool {
+        (**self).next_frame(frame)
+    }
+
+    fn seek(&mut self, frame: usize) -> bool {
+        (**self).seek(frame)
+    }
+
+    fn position(&self) -> Option<usize> {
+        (**self).position()
+    }
+
+    fn set_playback(&mut self, playback: JPlayback) -> bool {
+        (**self).set_playback(playback)
+    }
+
+    fn playback_rate(&self) -> f64 {
+        (**self).playback_rate()
+    }
+
+    fn set_effects(&mut self, chain: EffectChain) -> EffectChain {
+        (**self).set_effects(chain)
+    }
+}
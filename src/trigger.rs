@@ -0,0 +1,187 @@
+use serde::{Serialize, Deserialize};
+
+use crate::JGlobalHotkey;
+use crate::app::SoundId;
+use crate::mixer::VoiceId;
+use crate::pitch::JPlayback;
+use crate::sequence::SequenceId;
+
+
+/// How a hotkey plays its sound.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum JTriggerMode {
+    /// each press play the sound until its end.
+    #[default]
+    OneShot,
+    /// press again to stop the sound.
+    Toggle,
+    /// the sound stop when the hotkey is released.
+    Hold,
+    /// each press restart the sound from the beginning.
+    Retrigger,
+    /// loop the sound until the hotkey is pressed again.
+    Loop
+}
+
+
+/// How a hotkey chooses the sound it plays from its pool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum JPoolOrder {
+    #[default]
+    Random,
+    /// random but never the same sound twice in a row.
+    RandomNoRepeat,
+    /// every sound in turn.
+    RoundRobin,
+    /// random, each sound has a chance proportional to its weight.
+    Weighted
+}
+
+
+/// Sounds played by a hotkey.
+pub struct SoundPool {
+    /// sounds and their weight, the weight is only used by `JPoolOrder::Weighted`.
+    sounds: Vec<(SoundId, f32)>,
+    order: JPoolOrder,
+    last: Option<usize>,
+    rng: fastrand::Rng
+}
+
+impl SoundPool {
+
+    pub fn new(sounds: Vec<(SoundId, f32)>, order: JPoolOrder) -> Self {
+        SoundPool {
+            sounds,
+            order,
+            last: None,
+            rng: fastrand::Rng::new()
+        }
+    }
+
+    pub fn single(sound_id: SoundId) -> Self {
+        SoundPool::new(vec![(sound_id, 1.0)], JPoolOrder::RoundRobin)
+    }
+
+    pub fn next_sound(&mut self) -> Option<SoundId> {
+        let index = self.pick()?;
+        self.last = Some(index);
+        Some(self.sounds[index].0)
+    }
+
+    fn pick(&mut self) -> Option<usize> {
+        let len = self.sounds.len();
+        if len == 0 {
+            return None;
+        }
+        let index = match (self.order, self.last) {
+            (JPoolOrder::RoundRobin, Some(last)) => (last + 1) % len,
+            (JPoolOrder::RoundRobin, None) => 0,
+            (JPoolOrder::RandomNoRepeat, Some(last)) if len > 1 => {
+                // pick among the other sounds
+                let index = self.rng.usize(..len - 1);
+                if index >= last { index + 1 } else { index }
+            },
+            (JPoolOrder::Weighted, _) => {
+                let total: f32 = self.sounds.iter().map(|(_, w)| w.max(0.0)).sum();
+                if total <= 0.0 {
+                    return Some(self.rng.usize(..len));
+                }
+                let mut choice = self.rng.f32() * total;
+                self.sounds
+                    .iter()
+                    .position(|(_, w)| {
+                        choice -= w.max(0.0);
+                        choice < 0.0
+                    })
+                    // rounding can leave the choice right at the total
+                    .unwrap_or_else(|| self.sounds.iter().rposition(|(_, w)| *w > 0.0).unwrap_or(len - 1))
+            },
+            _ => self.rng.usize(..len)
+        };
+        Some(index)
+    }
+}
+
+
+/// Control of the voices playing.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum JTransportAction {
+    /// pause every voice, or resume them if they are all paused.
+    TogglePause,
+    /// move every voice to a position in milliseconds.
+    SeekTo(f32),
+    /// multiply the playback rate of every voice.
+    ChangeRate(f32),
+    /// shift the pitch of every voice by semitones.
+    ChangePitch(f32),
+    /// bring every voice back to the speed and pitch it started with.
+    ResetPlayback
+}
+
+
+/// What a hotkey plays.
+pub enum BindingTarget {
+    Sounds(SoundPool),
+    /// the loop mode doesn't loop sequences, it behaves like toggle.
+    Sequence(SequenceId),
+    Transport(JTransportAction),
+    /// start and stop a recording, the hold mode records while the hotkey is down.
+    Recording
+}
+
+
+pub struct HotkeyBinding {
+    pub hotkey: JGlobalHotkey,
+    pub target: BindingTarget,
+    pub mode: JTriggerMode,
+    /// applied on top of the sound playback settings.
+    pub playback: JPlayback,
+    /// voices started by this hotkey, some of them may have ended since.
+    pub voices: Vec<VoiceId>
+}
+
+impl HotkeyBinding {
+
+    pub fn new(hotkey: JGlobalHotkey, target: BindingTarget, mode: JTriggerMode) -> Self {
+        HotkeyBinding {
+            hotkey,
+            target,
+            mode,
+            playback: JPlayback::default(),
+            voices: Vec::new()
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod test {
+    use super::{JPoolOrder, SoundPool};
+    use crate::app::SoundId;
+
+    fn pool(weights: &[f32], order: JPoolOrder) -> SoundPool {
+        let sounds = weights.iter().enumerate().map(|(i, w)| (SoundId(i), *w)).collect();
+        let mut pool = SoundPool::new(sounds, order);
+        pool.rng = fastrand::Rng::with_seed(7);
+        pool
+    }
+
+    #[test]
+    fn sound_pool_test() {
+        let mut round_robin = pool(&[1.0; 3], JPoolOrder::RoundRobin);
+        let played: Vec<SoundId> = (0..4).filter_map(|_| round_robin.next_sound()).collect();
+        assert_eq!(played, [SoundId(0), SoundId(1), SoundId(2), SoundId(0)]);
+
+        let mut no_repeat = pool(&[1.0; 2], JPoolOrder::RandomNoRepeat);
+        let played: Vec<SoundId> = (0..100).filter_map(|_| no_repeat.next_sound()).collect();
+        assert!(played.windows(2).all(|w| w[0] != w[1]));
+
+        let mut weighted = pool(&[1.0, 0.0, 3.0], JPoolOrder::Weighted);
+        let played: Vec<SoundId> = (0..1000).filter_map(|_| weighted.next_sound()).collect();
+        assert!(!played.contains(&SoundId(1)));
+        let first = played.iter().filter(|s| **s == SoundId(0)).count();
+        assert!(first > 150 && first < 350, "{first}");
+
+        assert_eq!(pool(&[], JPoolOrder::Random).next_sound(), None);
+    }
+}